@@ -0,0 +1,26 @@
+use super::transform_values::TransformsValues;
+use crate::Result;
+use schemars::JsonSchema;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, JsonSchema)]
+/// define a folder to create (eg: an empty folder, not storable into git)
+pub(crate) struct DirectoryCfg {
+    /// path of the folder (relative to the destination of the template)
+    pub path: String,
+    /// permissions of the folder (octal, eg: `"750"`), only on unix and when the folder is created
+    pub mode: Option<String>,
+}
+
+impl TransformsValues for DirectoryCfg {
+    /// transforms path
+    fn transforms_values<F>(&self, render: &F) -> Result<Self>
+    where
+        F: Fn(&str) -> String,
+    {
+        let path = self.path.transforms_values(render)?;
+        Ok(DirectoryCfg {
+            path,
+            mode: self.mode.clone(),
+        })
+    }
+}
@@ -0,0 +1,91 @@
+use super::transform_values::TransformsValues;
+use crate::Result;
+use schemars::JsonSchema;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, JsonSchema)]
+/// define rules for the files of the template matching a path
+pub(crate) struct FileCfg {
+    /// pattern (glob) of the path of the files in the template, without the `.ffizer.hbs` extension
+    /// (eg: `**/*.md`)
+    pub path: String,
+    /// how the file is combined with the same file provided by the imported templates
+    #[serde(alias = "merge_strategy")]
+    pub strategy: Option<FileStrategy>,
+    /// line endings of the generated files (replace the `eol` of the template)
+    pub eol: Option<Eol>,
+    /// condition (rendered), the matching files and folders are generated only if it's `true`
+    pub when: Option<String>,
+    /// new path (rendered) of the matching file or folder, relative to the destination of the template
+    pub rename_to: Option<String>,
+    /// name of a variable (a list), the matching file or folder is generated for each item
+    pub for_each: Option<String>,
+    /// name of the variable of the current item (during the rendering), default `item`
+    pub item: Option<String>,
+}
+
+/// how a file is combined with the content of the same destination provided by the other layers.
+/// The layers are applied from the last imported to the root template
+/// (so the content of the imported templates is the "previous" content).
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStrategy {
+    /// replace the previous content (default for raw files)
+    Override,
+    /// keep the previous content, the file is used only if no other layer provides it
+    KeepFirst,
+    /// add the content after the previous content
+    Append,
+    /// add the content before the previous content
+    Prepend,
+    /// render the file with the previous content available as `{{ input_content }}`
+    /// (default for `.ffizer.hbs` files)
+    RenderChain,
+    /// parse the content (json, yaml or toml) and merge it with the previous content
+    /// and with the existing file (the values of the existing file are kept)
+    DeepMerge,
+}
+
+/// line endings of the generated text files
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Eol {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    Crlf,
+    /// the line endings of the platform (`crlf` on windows, `lf` otherwise)
+    Native,
+    /// keep the line endings of the template
+    #[default]
+    Preserve,
+}
+
+impl FileCfg {
+    /// the rule is applied if `when` is undefined or rendered as `true`
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.when
+            .as_ref()
+            .map(|v| v.trim().eq_ignore_ascii_case("true"))
+            .unwrap_or(true)
+    }
+}
+
+impl TransformsValues for FileCfg {
+    /// transforms when, rename_to
+    fn transforms_values<F>(&self, render: &F) -> Result<Self>
+    where
+        F: Fn(&str) -> String,
+    {
+        let when = self.when.transforms_values(render)?;
+        let rename_to = self.rename_to.transforms_values(render)?;
+        Ok(FileCfg {
+            path: self.path.clone(),
+            strategy: self.strategy,
+            eol: self.eol,
+            when,
+            rename_to,
+            for_each: self.for_each.clone(),
+            item: self.item.clone(),
+        })
+    }
+}
@@ -0,0 +1,28 @@
+use super::transform_values::TransformsValues;
+use crate::Result;
+use schemars::JsonSchema;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, JsonSchema)]
+/// define a command to format the generated files matching a path
+pub(crate) struct FormatterCfg {
+    /// pattern (glob) of the path of the files in the template, without the `.ffizer.hbs` extension
+    /// (eg: `**/*.rs`)
+    pub path: String,
+    /// command (run by the shell) that reads the content on stdin and writes the formatted content
+    /// on stdout, the path of the generated file is available as `$FFIZER_PATH` (eg: `rustfmt --emit stdout`)
+    pub cmd: String,
+}
+
+impl TransformsValues for FormatterCfg {
+    /// transforms cmd
+    fn transforms_values<F>(&self, render: &F) -> Result<Self>
+    where
+        F: Fn(&str) -> String,
+    {
+        let cmd = self.cmd.transforms_values(render)?;
+        Ok(FormatterCfg {
+            path: self.path.clone(),
+            cmd,
+        })
+    }
+}
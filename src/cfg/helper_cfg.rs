@@ -0,0 +1,23 @@
+use super::transform_values::TransformsValues;
+use crate::Result;
+use schemars::JsonSchema;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, JsonSchema)]
+/// define a helper usable into the files of the template, eg: `{{ license_header "MIT" }}`
+pub(crate) struct HelperCfg {
+    /// name of the helper
+    pub name: String,
+    /// handlebars template rendered by the helper, with the positional parameters as `params`
+    /// and the hash parameters by name (eg: `{{ params.[0] }}`, `{{ year }}`)
+    pub template: String,
+}
+
+impl TransformsValues for HelperCfg {
+    /// nothing to transform (the template is rendered when the helper is called)
+    fn transforms_values<F>(&self, _render: &F) -> Result<Self>
+    where
+        F: Fn(&str) -> String,
+    {
+        Ok(self.clone())
+    }
+}
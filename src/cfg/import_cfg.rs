@@ -1,6 +1,7 @@
 use super::transform_values::TransformsValues;
 use crate::Result;
 use schemars::JsonSchema;
+use std::collections::BTreeMap;
 
 #[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, JsonSchema)]
 /// define a template layer to import
@@ -8,6 +9,22 @@ pub(crate) struct ImportCfg {
     pub uri: String,
     pub rev: Option<String>,
     pub subfolder: Option<String>,
+    /// initialize and update the git submodules of the template (recursively)
+    #[serde(default)]
+    pub submodules: bool,
+    /// values of variables of the imported template (not asked),
+    /// rendered with the variables of the importing template
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// store the variables of the imported template under this namespace (eg: `{{ namespace.name }}`),
+    /// to import a same template several times with different values
+    pub namespace: Option<String>,
+    /// condition to apply the import (eg: `"{{ with_docker }}"`), evaluated after the variables
+    /// of the importing template are asked, the import is skipped if the result is not `true`
+    pub when: Option<String>,
+    /// folder (relative to the destination of the importing template) where the files
+    /// of the imported template are generated (eg: `services/{{ name }}`)
+    pub destination: Option<String>,
 }
 
 impl TransformsValues for ImportCfg {
@@ -23,6 +40,13 @@ impl TransformsValues for ImportCfg {
             uri,
             rev,
             subfolder,
+            submodules: self.submodules,
+            // rendered in the scope of the importing layer (after the variables are asked)
+            variables: self.variables.clone(),
+            namespace: self.namespace.clone(),
+            when: self.when.transforms_values(render)?,
+            // rendered in the scope of the importing layer (after the variables are asked)
+            destination: self.destination.clone(),
         })
     }
 }
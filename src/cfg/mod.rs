@@ -1,69 +1,281 @@
+mod directory_cfg;
+mod file_cfg;
+mod formatter_cfg;
+mod helper_cfg;
 mod ignore_cfg;
 mod import_cfg;
+mod patch_cfg;
+mod renamed_cfg;
 mod script_cfg;
 mod template_cfg;
 mod template_composite;
 mod transform_values;
 mod variable_cfg;
 
+pub(crate) use file_cfg::{Eol, FileStrategy};
+pub(crate) use script_cfg::OnFailure;
 pub(crate) use template_composite::*;
 pub(crate) use transform_values::*;
 pub(crate) use variable_cfg::*;
 
+use crate::patches::Patch;
 use crate::path_pattern::PathPattern;
 use crate::scripts::Script;
+use crate::source_file::FileLoop;
 use crate::source_loc::SourceLoc;
 use crate::source_uri::SourceUri;
-use crate::Result;
-use std::path::PathBuf;
+use crate::{Error, Result};
+use std::path::{Component, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 const TEMPLATE_CFG_FILENAME: &str = ".ffizer.yaml";
 pub const TEMPLATE_SAMPLES_DIRNAME: &str = ".ffizer.samples.d";
+const TEMPLATE_PARTIALS_DIRNAME: &str = ".ffizer.partials.d";
 
 impl template_cfg::TemplateCfg {
     pub(crate) fn find_ignores(&self) -> Result<Vec<PathPattern>> {
-        let trim_chars: &[_] = &['\r', '\n', ' ', '\t', '"', '\''];
-        let mut ignores = self
-            .ignores
-            .iter()
-            .map(|v| v.trim_matches(trim_chars))
-            .filter(|v| !v.is_empty())
-            .map(PathPattern::from_str)
-            .collect::<Result<Vec<PathPattern>>>()?;
+        let mut ignores = to_patterns(&self.ignores)?;
         let cfg_pattern = PathPattern::from_str(TEMPLATE_CFG_FILENAME)?;
         ignores.push(cfg_pattern);
         let samples_pattern = PathPattern::from_str(TEMPLATE_SAMPLES_DIRNAME)?;
         ignores.push(samples_pattern);
+        let partials_pattern = PathPattern::from_str(TEMPLATE_PARTIALS_DIRNAME)?;
+        ignores.push(partials_pattern);
         Ok(ignores)
     }
 
-    pub(crate) fn find_scripts(&self) -> Result<Vec<Script>> {
-        Ok(self
-            .scripts
+    /// the patterns of the files to render (without the suffix `.ffizer.hbs`)
+    pub(crate) fn find_render_patterns(&self) -> Result<Vec<PathPattern>> {
+        to_patterns(&self.render)
+    }
+
+    /// the patterns of the files to copy as is
+    pub(crate) fn find_raw_patterns(&self) -> Result<Vec<PathPattern>> {
+        to_patterns(&self.raw)
+    }
+
+    /// the folders to create (relative path, permissions)
+    pub(crate) fn find_directories(&self) -> Result<Vec<(PathBuf, Option<u32>)>> {
+        self.directories
             .iter()
-            .map(|v| Script {
-                message: v.message.clone().filter(|x| !x.is_empty()),
-                cmd: v.cmd.clone().filter(|x| !x.is_empty()),
+            .map(|v| {
+                let invalid = |msg: &str| Error::InvalidDirectory {
+                    path: v.path.clone(),
+                    msg: msg.to_owned(),
+                };
+                let path = relative_path(&v.path).ok_or_else(|| {
+                    invalid("the path should be relative (inside the destination folder)")
+                })?;
+                let mode = v
+                    .mode
+                    .as_ref()
+                    .map(|m| {
+                        u32::from_str_radix(m.trim().trim_start_matches("0o"), 8)
+                            .map_err(|_| invalid("the mode should be octal (eg: 755)"))
+                    })
+                    .transpose()?;
+                Ok((path, mode))
             })
-            .collect())
+            .collect()
     }
 
-    pub(crate) fn find_sourcelocs(&self) -> Result<Vec<SourceLoc>> {
-        self.imports
+    /// the files removed since a previous version of the template (relative path)
+    pub(crate) fn find_removed(&self) -> Result<Vec<PathBuf>> {
+        self.removed
+            .iter()
+            .map(|v| relative_path(v).ok_or_else(|| invalid_upgrade_path(v)))
+            .collect()
+    }
+
+    /// the files renamed since a previous version of the template (from, to)
+    pub(crate) fn find_renamed(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        self.renamed
             .iter()
             .map(|v| {
-                let uri = SourceUri::from_str(v.uri.as_str())?;
-                let subfolder = v.subfolder.as_ref().map(|x| PathBuf::from(x.as_str()));
-                let rev = v.rev.as_deref().unwrap_or("master").to_owned();
-                Ok(SourceLoc {
-                    uri,
-                    rev,
-                    subfolder,
+                let from = relative_path(&v.from).ok_or_else(|| invalid_upgrade_path(&v.from))?;
+                let to = relative_path(&v.to).ok_or_else(|| invalid_upgrade_path(&v.to))?;
+                Ok((from, to))
+            })
+            .collect()
+    }
+
+    /// the strategies defined for the files matching a path pattern (in the order of definition)
+    pub(crate) fn find_file_strategies(&self) -> Result<Vec<(PathPattern, FileStrategy)>> {
+        self.files
+            .iter()
+            .filter_map(|v| v.strategy.map(|strategy| (v.path.as_str(), strategy)))
+            .map(|(path, strategy)| PathPattern::from_str(path).map(|p| (p, strategy)))
+            .collect()
+    }
+
+    /// the line endings defined for the files matching a path pattern (in the order of definition)
+    pub(crate) fn find_file_eols(&self) -> Result<Vec<(PathPattern, Eol)>> {
+        self.files
+            .iter()
+            .filter_map(|v| v.eol.map(|eol| (v.path.as_str(), eol)))
+            .map(|(path, eol)| PathPattern::from_str(path).map(|p| (p, eol)))
+            .collect()
+    }
+
+    /// the commands to format the files matching a path pattern (in the order of definition)
+    pub(crate) fn find_formatters(&self) -> Result<Vec<(PathPattern, String)>> {
+        self.formatters
+            .iter()
+            .filter(|v| !v.cmd.trim().is_empty())
+            .map(|v| PathPattern::from_str(&v.path).map(|p| (p, v.cmd.trim().to_owned())))
+            .collect()
+    }
+
+    /// the patterns of the files (and folders) to exclude: a rule with a `when` not rendered as `true`
+    pub(crate) fn find_file_exclusions(&self) -> Result<Vec<PathPattern>> {
+        self.files
+            .iter()
+            .filter(|v| !v.is_enabled())
+            .map(|v| PathPattern::from_str(&v.path))
+            .collect()
+    }
+
+    /// the patterns of the files (and folders) to rename, with their new path
+    pub(crate) fn find_file_renames(&self) -> Result<Vec<(PathPattern, PathBuf)>> {
+        self.files
+            .iter()
+            .filter_map(|v| v.rename_to.as_ref().map(|rename_to| (v, rename_to)))
+            .map(|(v, rename_to)| {
+                let to = relative_path(rename_to).ok_or_else(|| Error::InvalidFileRule {
+                    path: v.path.clone(),
+                    msg: "`rename_to` should be relative (inside the destination folder)"
+                        .to_owned(),
+                })?;
+                PathPattern::from_str(&v.path).map(|p| (p, to))
+            })
+            .collect()
+    }
+
+    /// the patterns of the files (and folders) to generate for each item of a list
+    pub(crate) fn find_file_loops(&self) -> Result<Vec<(PathPattern, FileLoop)>> {
+        self.files
+            .iter()
+            .filter_map(|v| v.for_each.as_ref().map(|variable| (v, variable)))
+            .map(|(v, variable)| {
+                let file_loop = FileLoop {
+                    variable: variable.trim().to_owned(),
+                    item_name: v
+                        .item
+                        .as_deref()
+                        .map(|s| s.trim())
+                        .filter(|s| !s.is_empty())
+                        .unwrap_or("item")
+                        .to_owned(),
+                    item: None,
+                };
+                PathPattern::from_str(&v.path).map(|p| (p, file_loop))
+            })
+            .collect()
+    }
+
+    pub(crate) fn find_patches(&self) -> Result<Vec<Patch>> {
+        self.patches
+            .iter()
+            .map(|v| {
+                Patch::new(
+                    &v.path,
+                    &v.content,
+                    v.after.as_deref().filter(|x| !x.is_empty()),
+                    v.before.as_deref().filter(|x| !x.is_empty()),
+                )
+            })
+            .collect()
+    }
+
+    /// the scripts to run, except the disabled ones (see `ScriptCfg::when`)
+    pub(crate) fn find_scripts(&self) -> Result<Vec<Script>> {
+        self.scripts
+            .iter()
+            .filter(|v| v.is_enabled())
+            .map(|v| {
+                let cwd = match v.cwd.as_deref().filter(|x| !x.trim().is_empty()) {
+                    Some(cwd) => Some(relative_path(cwd).ok_or_else(|| Error::InvalidScript {
+                        msg: format!(
+                            "the cwd {:?} should be relative (inside the destination folder)",
+                            cwd
+                        ),
+                    })?),
+                    None => None,
+                };
+                Ok(Script {
+                    message: v.message.clone().filter(|x| !x.is_empty()),
+                    cmd: v.cmd.clone().filter(|x| !x.trim().is_empty()),
+                    cwd,
+                    env: v.env.clone(),
+                    shell: v.shell.clone().filter(|x| !x.trim().is_empty()),
+                    timeout: v.timeout.map(Duration::from_secs),
+                    on_failure: v.on_failure,
                 })
             })
             .collect()
     }
+
+    /// the templates to import, except the conditional ones (see `ImportCfg::when`)
+    pub(crate) fn find_sourcelocs(&self) -> Result<Vec<SourceLoc>> {
+        self.imports
+            .iter()
+            .filter(|v| v.when.is_none())
+            .map(|v| v.to_sourceloc())
+            .collect()
+    }
+}
+
+impl import_cfg::ImportCfg {
+    pub(crate) fn to_sourceloc(&self) -> Result<SourceLoc> {
+        let uri = SourceUri::from_str(self.uri.as_str())?;
+        let subfolder = self.subfolder.as_ref().map(|x| PathBuf::from(x.as_str()));
+        let rev = self.rev.as_deref().unwrap_or("master").to_owned();
+        Ok(SourceLoc {
+            uri,
+            rev,
+            subfolder,
+            submodules: self.submodules,
+        })
+    }
+
+    /// the import is applied if `when` is undefined or rendered as `true`
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.when
+            .as_ref()
+            .map(|v| v.trim().eq_ignore_ascii_case("true"))
+            .unwrap_or(true)
+    }
+}
+
+fn to_patterns(values: &[String]) -> Result<Vec<PathPattern>> {
+    let trim_chars: &[_] = &['\r', '\n', ' ', '\t', '"', '\''];
+    values
+        .iter()
+        .map(|v| v.trim_matches(trim_chars))
+        .filter(|v| !v.is_empty())
+        .map(PathPattern::from_str)
+        .collect()
+}
+
+/// the path if it is relative and inside the destination folder
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(path.trim());
+    let inside = path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    let path = path
+        .components()
+        .filter(|c| c != &Component::CurDir)
+        .collect::<PathBuf>();
+    (!path.as_os_str().is_empty() && inside).then_some(path)
+}
+
+fn invalid_upgrade_path(path: &str) -> Error {
+    Error::InvalidUpgradePath {
+        path: path.to_owned(),
+        msg: "the path should be relative (inside the destination folder)".to_owned(),
+    }
 }
 
 pub fn provide_json_schema() -> Result<String> {
@@ -0,0 +1,35 @@
+use super::transform_values::TransformsValues;
+use crate::Result;
+use schemars::JsonSchema;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, JsonSchema)]
+/// define a snippet to insert into a file of the destination
+pub(crate) struct PatchCfg {
+    /// path of the file to patch (relative to the destination of the template), created if missing
+    pub path: String,
+    /// the snippet to insert (a line or a block of lines), not inserted if already present
+    pub content: String,
+    /// insert the snippet after the first line matching this regex
+    pub after: Option<String>,
+    /// insert the snippet before the first line matching this regex
+    pub before: Option<String>,
+}
+
+impl TransformsValues for PatchCfg {
+    /// transforms path, content, after & before
+    fn transforms_values<F>(&self, render: &F) -> Result<Self>
+    where
+        F: Fn(&str) -> String,
+    {
+        let path = self.path.transforms_values(render)?;
+        let content = self.content.transforms_values(render)?;
+        let after = self.after.transforms_values(render)?;
+        let before = self.before.transforms_values(render)?;
+        Ok(PatchCfg {
+            path,
+            content,
+            after,
+            before,
+        })
+    }
+}
@@ -0,0 +1,25 @@
+use super::transform_values::TransformsValues;
+use crate::Result;
+use schemars::JsonSchema;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, JsonSchema)]
+/// define a file renamed since a previous version of the template,
+/// the existing file (generated by the previous version) is moved to its new path
+pub(crate) struct RenamedCfg {
+    /// previous path of the file (relative to the destination of the template)
+    pub from: String,
+    /// new path of the file (relative to the destination of the template)
+    pub to: String,
+}
+
+impl TransformsValues for RenamedCfg {
+    /// transforms from, to
+    fn transforms_values<F>(&self, render: &F) -> Result<Self>
+    where
+        F: Fn(&str) -> String,
+    {
+        let from = self.from.transforms_values(render)?;
+        let to = self.to.transforms_values(render)?;
+        Ok(RenamedCfg { from, to })
+    }
+}
@@ -1,6 +1,7 @@
 use super::transform_values::TransformsValues;
 use crate::Result;
 use schemars::JsonSchema;
+use std::collections::BTreeMap;
 
 #[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, JsonSchema)]
 pub(crate) struct ScriptCfg {
@@ -8,16 +9,83 @@ pub(crate) struct ScriptCfg {
     pub(crate) message: Option<String>,
     /// command to execute
     pub(crate) cmd: Option<String>,
+    /// condition (rendered), the script is run if undefined or rendered as `true`
+    pub(crate) when: Option<String>,
+    /// folder (rendered) where to run the command, relative to the destination of the template
+    pub(crate) cwd: Option<String>,
+    /// environment variables (values are rendered) added to run the command
+    #[serde(default)]
+    pub(crate) env: BTreeMap<String, String>,
+    /// program used to run the command (eg: `bash`, `pwsh`), default `sh` (`cmd.exe` on windows),
+    /// the command is written into a file with the extension expected by the shell (eg: `.ps1` for `pwsh`)
+    pub(crate) shell: Option<String>,
+    /// maximum duration of the command (in seconds), the command is killed after
+    pub(crate) timeout: Option<u64>,
+    /// what to do when the command fails (or timeouts)
+    #[serde(default)]
+    pub(crate) on_failure: OnFailure,
+}
+
+/// what to do when a command of the scripts fails
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    /// stop the apply (exit with an error)
+    Abort,
+    /// log a warning and continue
+    #[default]
+    Warn,
+    /// continue silently
+    Ignore,
+}
+
+impl ScriptCfg {
+    /// the script is run if `when` is undefined or rendered as `true`
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.when
+            .as_ref()
+            .map(|v| v.trim().eq_ignore_ascii_case("true"))
+            .unwrap_or(true)
+    }
 }
 
 impl TransformsValues for ScriptCfg {
-    /// transforms default_value & ignore
+    /// transforms message, cmd, when, cwd & env
     fn transforms_values<F>(&self, render: &F) -> Result<Self>
     where
         F: Fn(&str) -> String,
     {
         let message = self.message.transforms_values(render)?;
         let cmd = self.cmd.transforms_values(render)?;
-        Ok(ScriptCfg { message, cmd })
+        let when = self.when.transforms_values(render)?;
+        let cwd = self.cwd.transforms_values(render)?;
+        let env = self
+            .env
+            .iter()
+            .map(|(k, v)| v.transforms_values(render).map(|v| (k.clone(), v)))
+            .collect::<Result<_>>()?;
+        Ok(ScriptCfg {
+            message,
+            cmd,
+            when,
+            cwd,
+            env,
+            shell: self.shell.clone(),
+            timeout: self.timeout,
+            on_failure: self.on_failure,
+        })
     }
 }
@@ -1,11 +1,18 @@
 use super::transform_values::TransformsValues;
+use crate::engine::EngineKind;
 use crate::error::*;
 use schemars::JsonSchema;
 use std::fs;
 use std::path::Path;
 
+use super::directory_cfg::DirectoryCfg;
+use super::file_cfg::{Eol, FileCfg};
+use super::formatter_cfg::FormatterCfg;
+use super::helper_cfg::HelperCfg;
 use super::ignore_cfg::IgnoreCfg;
 use super::import_cfg::ImportCfg;
+use super::patch_cfg::PatchCfg;
+use super::renamed_cfg::RenamedCfg;
 use super::script_cfg::ScriptCfg;
 use super::variable_cfg::VariableCfg;
 
@@ -16,8 +23,32 @@ pub struct TemplateCfg {
     pub(crate) variables: Vec<VariableCfg>,
     /// list of path from the current template to ignore
     pub(crate) ignores: Vec<IgnoreCfg>,
+    /// list of path (glob) of the files to render (like the `.ffizer.hbs` files) without renaming them
+    pub(crate) render: Vec<String>,
+    /// list of path (glob) of the files to copy as is (like the `.ffizer.raw` files), applied after `render`
+    pub(crate) raw: Vec<String>,
+    /// template engine (`handlebars` or `jinja`) of the values of this configuration, of the names of the files
+    /// and of the files to render without suffix of engine (`.ffizer.hbs` or `.ffizer.j2`), default: `handlebars`
+    pub(crate) engine: EngineKind,
+    /// list of folders to create (eg: empty folders)
+    pub(crate) directories: Vec<DirectoryCfg>,
+    /// list of rules for the files of the template (the first rule matching a file is used)
+    pub(crate) files: Vec<FileCfg>,
+    /// line endings of the generated text files (default: `preserve`, or defined by the `.gitattributes` of the destination)
+    pub(crate) eol: Option<Eol>,
+    /// list of helpers (defined by a handlebars template) usable into the files of the template
+    pub(crate) helpers: Vec<HelperCfg>,
+    /// list of commands to format the generated files (before the comparison with the existing files)
+    pub(crate) formatters: Vec<FormatterCfg>,
     /// list of template to import and to apply as part of this template
     pub(crate) imports: Vec<ImportCfg>,
+    /// list of snippets to insert into files of the destination (after the files are generated)
+    pub(crate) patches: Vec<PatchCfg>,
+    /// list of files removed since a previous version of the template,
+    /// deleted from the destination if unmodified since their generation (see `--record`)
+    pub(crate) removed: Vec<String>,
+    /// list of files renamed since a previous version of the template
+    pub(crate) renamed: Vec<RenamedCfg>,
     /// list of the scripts to apply at end of generation
     pub(crate) scripts: Vec<ScriptCfg>,
     /// set to true if the template content is under a `template` folder (not mixed with metadata)
@@ -52,12 +83,32 @@ impl TransformsValues for TemplateCfg {
     {
         let variables = self.variables.clone();
         let ignores = self.ignores.transforms_values(render)?;
+        let render_patterns = self.render.transforms_values(render)?;
+        let raw = self.raw.transforms_values(render)?;
+        let directories = self.directories.transforms_values(render)?;
+        let files = self.files.transforms_values(render)?;
+        let helpers = self.helpers.transforms_values(render)?;
+        let formatters = self.formatters.transforms_values(render)?;
         let imports = self.imports.transforms_values(render)?;
+        let patches = self.patches.transforms_values(render)?;
+        let removed = self.removed.transforms_values(render)?;
+        let renamed = self.renamed.transforms_values(render)?;
         let scripts = self.scripts.transforms_values(render)?;
         Ok(TemplateCfg {
             variables,
             ignores,
+            render: render_patterns,
+            raw,
+            engine: self.engine,
+            directories,
+            files,
+            eol: self.eol,
+            helpers,
+            formatters,
             imports,
+            patches,
+            removed,
+            renamed,
             scripts,
             use_template_dir: self.use_template_dir,
         })
@@ -225,6 +276,139 @@ mod tests {
         assert_that!(&actual.use_template_dir).is_true();
     }
 
+    #[test]
+    fn test_find_sourcelocs_skip_conditional_imports() {
+        let cfg_str = r#"
+        imports:
+            - uri: "foo"
+            - uri: "bar"
+              when: "{{ with_bar }}"
+        "#;
+        let actual = serde_yaml::from_str::<TemplateCfg>(cfg_str).unwrap();
+        let locs = actual.find_sourcelocs().unwrap();
+        assert_that!(&locs).has_length(1);
+        assert_that!(&locs[0].uri.raw).is_equal_to("foo".to_owned());
+        assert_that!(actual.imports[0].is_enabled()).is_true();
+        assert_that!(actual.imports[1].is_enabled()).is_false();
+        let render = |v: &str| v.replace("{{ with_bar }}", "true");
+        assert_that!(actual.imports[1]
+            .transforms_values(&render)
+            .unwrap()
+            .is_enabled())
+        .is_true();
+    }
+
+    #[test]
+    fn test_find_directories() {
+        let cfg_str = r#"
+        directories:
+            - path: logs
+            - path: "data/{{ name }}"
+              mode: "0750"
+        "#;
+        let actual = serde_yaml::from_str::<TemplateCfg>(cfg_str).unwrap();
+        assert_that!(actual.find_directories().unwrap()).is_equal_to(vec![
+            (std::path::PathBuf::from("logs"), None),
+            (std::path::PathBuf::from("data/{{ name }}"), Some(0o750)),
+        ]);
+        for invalid in ["path: ../logs", "path: logs\n              mode: rwx"] {
+            let cfg_str = format!("directories:\n            - {}", invalid);
+            let actual = serde_yaml::from_str::<TemplateCfg>(&cfg_str).unwrap();
+            assert_that!(actual.find_directories()).is_err();
+        }
+    }
+
+    #[test]
+    fn test_find_render_and_raw_patterns() {
+        let cfg_str = r#"
+        render:
+            - "src/**/*.rs"
+            - ""
+        raw:
+            - "src/**/generated.rs"
+        "#;
+        let actual = serde_yaml::from_str::<TemplateCfg>(cfg_str).unwrap();
+        let render = actual.find_render_patterns().unwrap();
+        assert_that!(&render).has_length(1);
+        assert_that!(render[0].is_match("src/foo/main.rs")).is_true();
+        let raw = actual.find_raw_patterns().unwrap();
+        assert_that!(raw[0].is_match("src/foo/generated.rs")).is_true();
+    }
+
+    #[test]
+    fn test_find_file_eols() {
+        let cfg_str = r#"
+        eol: lf
+        files:
+            - path: "**/*.bat"
+              eol: crlf
+            - path: "**/*.md"
+              strategy: append
+        "#;
+        let actual = serde_yaml::from_str::<TemplateCfg>(cfg_str).unwrap();
+        assert_that!(&actual.eol).is_equal_to(Some(Eol::Lf));
+        let eols = actual.find_file_eols().unwrap();
+        assert_that!(&eols).has_length(1);
+        assert_that!(eols[0].0.is_match("scripts/run.bat")).is_true();
+        assert_that!(&eols[0].1).is_equal_to(Eol::Crlf);
+    }
+
+    #[test]
+    fn test_find_file_exclusions_and_renames() {
+        let cfg_str = r#"
+        files:
+            - path: ".github"
+              when: "{{ with_ci }}"
+            - path: "docs/**"
+              when: "true"
+            - path: "NOTES.md"
+              rename_to: "docs/{{ name }}.md"
+        "#;
+        let actual = serde_yaml::from_str::<TemplateCfg>(cfg_str).unwrap();
+        let exclusions = actual.find_file_exclusions().unwrap();
+        assert_that!(exclusions
+            .iter()
+            .map(|p| p.raw.as_str())
+            .collect::<Vec<_>>())
+        .is_equal_to(vec![".github"]);
+        let render = |v: &str| v.replace("{{ with_ci }}", "true");
+        let rendered = actual.transforms_values(&render).unwrap();
+        assert_that!(rendered.find_file_exclusions().unwrap()).is_empty();
+        let renames = rendered.find_file_renames().unwrap();
+        assert_that!(renames[0].1).is_equal_to(std::path::PathBuf::from("docs/{{ name }}.md"));
+        let invalid = "files: [{path: a.md, rename_to: /tmp/a.md}]";
+        let actual = serde_yaml::from_str::<TemplateCfg>(invalid).unwrap();
+        assert_that!(actual.find_file_renames()).is_err();
+    }
+
+    #[test]
+    fn test_find_removed_and_renamed() {
+        let cfg_str = r#"
+        removed:
+            - old.txt
+        renamed:
+            - from: "doc/{{ name }}.md"
+              to: "docs/{{ name }}.md"
+        "#;
+        let actual = serde_yaml::from_str::<TemplateCfg>(cfg_str).unwrap();
+        assert_that!(actual.find_removed().unwrap())
+            .is_equal_to(vec![std::path::PathBuf::from("old.txt")]);
+        assert_that!(actual.find_renamed().unwrap()).is_equal_to(vec![(
+            std::path::PathBuf::from("doc/{{ name }}.md"),
+            std::path::PathBuf::from("docs/{{ name }}.md"),
+        )]);
+        for invalid in [
+            "removed: [\"/etc/hosts\"]",
+            "renamed: [{from: a.txt, to: ../a.txt}]",
+        ] {
+            let actual = serde_yaml::from_str::<TemplateCfg>(invalid).unwrap();
+            assert_that!(actual
+                .find_removed()
+                .and_then(|_| actual.find_renamed().map(|_| ())))
+            .is_err();
+        }
+    }
+
     #[test]
     fn test_accept_ignores_with_values() {
         let cfg_in_str = r#"
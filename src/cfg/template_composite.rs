@@ -1,22 +1,42 @@
+use super::file_cfg::FileStrategy;
 use super::template_cfg::TemplateCfg;
 use super::transform_values::TransformsValues;
 use super::variable_cfg::VariableCfg;
+use super::TEMPLATE_PARTIALS_DIRNAME;
+use crate::cli_opt::{GitOpts, ImportConflictPolicy, SymlinkPolicy};
+use crate::engine::{value_renderer, EngineKind, Engines};
+use crate::error::Error;
 use crate::files;
+use crate::files::ChildPath;
 use crate::graph::Graph;
+use crate::patches::Patch;
 use crate::scripts::Script;
-use crate::source_file::SourceFile;
+use crate::source_file::{SourceFile, SourceFileMetadata};
 use crate::source_loc::SourceLoc;
+use crate::template_helpers::{self, TemplateHelper};
+use crate::text;
 use crate::Result;
 use crate::Variables;
-use handlebars_misc_helpers::new_hbs;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::path::{Component, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use tracing::{debug, instrument, span, warn, Level};
 #[derive(Debug, Clone)]
 pub struct TemplateLayer {
     order: usize,
     loc: SourceLoc,
     cfg: TemplateCfg,
+    /// namespace where the values of the variables of the layer are stored
+    namespace: Vec<String>,
+    /// order of the layer that imports this layer
+    parent: Option<usize>,
+    /// values of variables provided by the import
+    imported_values: BTreeMap<String, String>,
+    /// folder where the files of the layer are generated (relative to the one of the parent)
+    destination: Option<String>,
 }
 
 impl TransformsValues for TemplateLayer {
@@ -29,56 +49,166 @@ impl TransformsValues for TemplateLayer {
             order: self.order,
             loc: self.loc.clone(),
             cfg,
+            namespace: self.namespace.clone(),
+            parent: self.parent,
+            imported_values: self.imported_values.clone(),
+            destination: self.destination.clone(),
         })
     }
 }
 
+impl TemplateLayer {
+    fn key(&self) -> LayerKey {
+        LayerKey {
+            loc: self.loc.clone(),
+            namespace: self.namespace.clone(),
+            imported_values: self.imported_values.clone(),
+            destination: self.destination.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TemplateComposite {
     layers: Vec<TemplateLayer>,
+    imports: Imports,
 }
 
 impl TemplateComposite {
     pub fn from_src(
         variables: &Variables,
         offline: bool,
+        git_opts: &GitOpts,
+        import_conflict: &ImportConflictPolicy,
         src: &SourceLoc,
     ) -> Result<TemplateComposite> {
         let mut templates = HashMap::new();
-        deep_download(variables, offline, src, &mut templates)?;
-        let layers = templates
-            .find_edges_ordered_by_depth(src)
-            .into_iter()
-            .enumerate()
-            .map(|(i, k)| {
-                let v = templates.get(&k).expect("should exist").clone();
-                TemplateLayer {
-                    order: i,
-                    loc: k,
-                    cfg: v,
+        deep_download(variables, offline, git_opts, src, &mut templates)?;
+        let imports = Imports {
+            variables: variables.clone(),
+            offline,
+            git_opts: git_opts.clone(),
+            import_conflict: import_conflict.clone(),
+            root: LayerKey::root(src),
+            templates,
+            conditionals: HashMap::new(),
+        };
+        let layers = imports.find_layers()?;
+        debug!(?layers);
+        Ok(TemplateComposite { layers, imports })
+    }
+
+    /// evaluate the conditional imports (`when`) of the layers with the variables (already asked),
+    /// and download the enabled ones, return true if layers are added (their variables should be asked)
+    pub fn include_conditional_imports(&mut self, variables: &Variables) -> Result<bool> {
+        let layers_variables = self.find_layers_variables(variables)?;
+        let engines = Engines::default();
+        let mut changed = false;
+        for (layer, mut scope) in self.layers.iter().zip(layers_variables) {
+            scope.insert("ffizer_src_uri", layer.loc.uri.raw.clone())?;
+            scope.insert("ffizer_src_rev", layer.loc.rev.clone())?;
+            let render = value_renderer(engines.get(layer.cfg.engine), &scope, true);
+            for (i, import) in layer.cfg.imports.iter().enumerate() {
+                let k = (layer.key(), i);
+                if import.when.is_none() || self.imports.conditionals.contains_key(&k) {
+                    continue;
                 }
+                let import = import.transforms_values(&render)?;
+                let loc = if import.is_enabled() {
+                    let loc = import.to_sourceloc()?;
+                    deep_download(
+                        &self.imports.variables,
+                        self.imports.offline,
+                        &self.imports.git_opts,
+                        &loc,
+                        &mut self.imports.templates,
+                    )?;
+                    changed = true;
+                    Some(loc)
+                } else {
+                    None
+                };
+                debug!(layer = ?layer.loc, when = ?import.when, ?loc, "conditional import");
+                self.imports.conditionals.insert(k, loc);
+            }
+        }
+        if changed {
+            self.layers = self.imports.find_layers()?;
+            debug!(layers = ?self.layers);
+        }
+        Ok(changed)
+    }
+
+    /// the variables of every layer (in order), a variable is asked only if its value
+    /// is not yet defined into the namespace of the layer (the first definition is kept)
+    pub fn find_variablecfgs(&self) -> Result<Vec<LayerVariableCfgs>> {
+        Ok(self
+            .layers
+            .iter()
+            .map(|layer| LayerVariableCfgs {
+                namespace: layer.namespace.clone(),
+                parent: layer.parent,
+                imported_values: layer.imported_values.clone(),
+                variables: layer.cfg.variables.clone(),
+                engine: layer.cfg.engine,
+                parent_engine: layer
+                    .parent
+                    .and_then(|i| self.layers.get(i))
+                    .map(|p| p.cfg.engine)
+                    .unwrap_or_default(),
             })
-            .collect::<Vec<_>>();
-        debug!(?layers);
-        Ok(TemplateComposite { layers })
+            .collect())
     }
 
-    pub fn find_variablecfgs(&self) -> Result<Vec<VariableCfg>> {
-        let mut back = vec![];
-        let mut names = HashSet::new();
+    /// the variables to render each layer (by order)
+    pub fn find_layers_variables(&self, variables: &Variables) -> Result<Vec<Variables>> {
+        let mut back = Vec::with_capacity(self.layers.len());
+        for layer in self.find_variablecfgs()? {
+            let scope = layer.scope(variables, &back)?;
+            back.push(scope);
+        }
+        Ok(back)
+    }
+
+    /// the folder (relative to the destination) of each layer (by order),
+    /// rendered with the variables of the importing layer
+    pub fn find_layers_destinations(&self, layers_variables: &[Variables]) -> Result<Vec<PathBuf>> {
+        let engines = Engines::default();
+        let mut back: Vec<PathBuf> = Vec::with_capacity(self.layers.len());
         for layer in &self.layers {
-            let _span_ = span!(Level::DEBUG, "find_variablecfgs", layer = ?layer).entered();
-            for variable in layer.cfg.variables.clone() {
-                if !names.contains(&variable.name) {
-                    names.insert(variable.name.clone());
-                    back.push(variable.clone());
+            let parent_destination = layer
+                .parent
+                .and_then(|i| back.get(i))
+                .cloned()
+                .unwrap_or_default();
+            let destination = match (&layer.destination, layer.parent) {
+                (Some(template), Some(parent)) => {
+                    let rendered = engines.get(self.layers[parent].cfg.engine).render(
+                        template,
+                        &layers_variables[parent],
+                        &format!("define destination of the import of '{}'", layer.loc),
+                    )?;
+                    let rendered = PathBuf::from(rendered.trim());
+                    if !rendered
+                        .components()
+                        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+                    {
+                        return Err(Error::ImportDestination {
+                            uri: layer.loc.uri.raw.clone(),
+                            destination: rendered.to_string_lossy().to_string(),
+                        });
+                    }
+                    parent_destination.join(rendered)
                 }
-            }
+                _ => parent_destination,
+            };
+            back.push(destination);
         }
         Ok(back)
     }
 
-    pub fn find_sourcefiles(&self) -> Result<Vec<SourceFile>> {
+    pub fn find_sourcefiles(&self, symlinks: SymlinkPolicy) -> Result<Vec<SourceFile>> {
+        let follow_links = symlinks == SymlinkPolicy::Follow;
         let mut back = vec![];
         for layer in &self.layers {
             let _span_ = span!(Level::DEBUG, "find_sourcefiles", layer = ?layer).entered();
@@ -88,12 +218,171 @@ impl TemplateComposite {
             } else {
                 ""
             };
+            let strategies = layer.cfg.find_file_strategies()?;
+            let exclusions = layer.cfg.find_file_exclusions()?;
+            let renames = layer.cfg.find_file_renames()?;
+            let loops = layer.cfg.find_file_loops()?;
+            let render_patterns = layer.cfg.find_render_patterns()?;
+            let eols = layer.cfg.find_file_eols()?;
+            let formatters = layer.cfg.find_formatters()?;
+            let raw_patterns = layer.cfg.find_raw_patterns()?;
             let path = layer.loc.as_local_path()?.join(template_dir);
             debug!(ignores = ?ignores);
-            for childpath in files::find_childpaths(path, ignores) {
-                back.push(SourceFile::from((childpath, layer.order)));
+            for childpath in files::find_childpaths(&path, ignores, follow_links) {
+                let relative = files::remove_special_suffix(&childpath.relative)?;
+                // a rule on a folder is also applied to its content
+                if relative
+                    .ancestors()
+                    .any(|a| exclusions.iter().any(|p| p.is_match(a)))
+                {
+                    continue;
+                }
+                let is_raw_by_suffix = files::is_ffizer_raw(&childpath.relative);
+                let mut source_file = SourceFile::new(childpath, layer.order, follow_links);
+                // the suffix of the file selects its engine, else it's the one of the template
+                if EngineKind::from_path(&source_file.childpath.relative).is_none() {
+                    source_file.engine = layer.cfg.engine;
+                }
+                if raw_patterns.iter().any(|p| p.is_match(&relative)) {
+                    source_file.set_renderable(false);
+                } else if !is_raw_by_suffix && render_patterns.iter().any(|p| p.is_match(&relative))
+                {
+                    source_file.set_renderable(true);
+                }
+                if matches!(
+                    source_file.metadata,
+                    SourceFileMetadata::RenderableFile { .. }
+                ) && text::is_binary_file(&PathBuf::from(&source_file.childpath))?
+                {
+                    warn!(path = ?source_file.childpath, "binary file copied as is (not rendered)");
+                    source_file.set_renderable(false);
+                }
+                if source_file.metadata.is_file() {
+                    if let Some((_, strategy)) =
+                        strategies.iter().find(|(p, _)| p.is_match(&relative))
+                    {
+                        source_file.strategy = *strategy;
+                    }
+                    source_file.eol = eols
+                        .iter()
+                        .find(|(p, _)| p.is_match(&relative))
+                        .map(|(_, eol)| *eol);
+                    source_file.template_eol = layer.cfg.eol;
+                    source_file.formatter = formatters
+                        .iter()
+                        .find(|(p, _)| p.is_match(&relative))
+                        .map(|(_, cmd)| cmd.clone());
+                }
+                source_file.rename_to = relative.ancestors().find_map(|a| {
+                    renames.iter().find(|(p, _)| p.is_match(a)).map(|(_, to)| {
+                        let rest = source_file
+                            .childpath
+                            .relative
+                            .components()
+                            .skip(a.components().count())
+                            .collect::<PathBuf>();
+                        if rest.as_os_str().is_empty() {
+                            to.clone()
+                        } else {
+                            to.join(rest)
+                        }
+                    })
+                });
+                source_file.for_each = relative.ancestors().find_map(|a| {
+                    loops
+                        .iter()
+                        .find(|(p, _)| p.is_match(a))
+                        .map(|(_, file_loop)| file_loop.clone())
+                });
+                back.push(source_file);
+            }
+            for (relative, mode) in layer.cfg.find_directories()? {
+                back.push(SourceFile {
+                    childpath: ChildPath {
+                        base: path.clone(),
+                        relative,
+                    },
+                    layer_order: layer.order,
+                    metadata: SourceFileMetadata::DeclaredDir { mode },
+                    strategy: FileStrategy::Override,
+                    rename_to: None,
+                    for_each: None,
+                    eol: None,
+                    template_eol: None,
+                    formatter: None,
+                    engine: layer.cfg.engine,
+                });
+            }
+        }
+        Ok(back)
+    }
+
+    /// register the helpers (handlebars only) and the partials (files of the folder `.ffizer.partials.d`) of the layers,
+    /// the ones of the root template replace the ones (with the same name) of its imports
+    pub(crate) fn register_helpers_and_partials(&self, engines: &mut Engines) -> Result<()> {
+        for layer in self.layers.iter().rev() {
+            let partials = layer.loc.as_local_path()?.join(TEMPLATE_PARTIALS_DIRNAME);
+            if partials.is_dir() {
+                template_helpers::register_partials(engines, &partials)?;
+            }
+            for helper in &layer.cfg.helpers {
+                engines.handlebars.register_helper(
+                    &helper.name,
+                    Box::new(TemplateHelper {
+                        template: helper.template.clone(),
+                    }),
+                );
             }
         }
+        Ok(())
+    }
+
+    /// the label of each layer (by order), to show where the files come from
+    pub fn find_layers_labels(&self) -> Vec<String> {
+        self.layers
+            .iter()
+            .map(|l| {
+                if l.namespace.is_empty() {
+                    l.loc.to_string()
+                } else {
+                    format!("{} as {}", l.loc, l.namespace.join("."))
+                }
+            })
+            .collect()
+    }
+
+    /// the patches of each layer (by order)
+    pub fn find_patches(&self) -> Result<Vec<Vec<Patch>>> {
+        self.layers.iter().map(|t| t.cfg.find_patches()).collect()
+    }
+
+    /// the files removed (by any layer) since a previous version, relative to the destination
+    pub fn find_removed(&self, layers_destinations: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let mut back = vec![];
+        for (layer, destination) in self.layers.iter().zip(layers_destinations) {
+            back.extend(
+                layer
+                    .cfg
+                    .find_removed()?
+                    .iter()
+                    .map(|p| destination.join(p)),
+            );
+        }
+        Ok(back)
+    }
+
+    /// the files renamed (by any layer) since a previous version, relative to the destination
+    pub fn find_renamed(&self, layers_destinations: &[PathBuf]) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut back = vec![];
+        for (layer, destination) in self.layers.iter().zip(layers_destinations) {
+            back.extend(
+                layer
+                    .cfg
+                    .find_renamed()?
+                    .iter()
+                    .map(|(from, to)| (destination.join(from), destination.join(to))),
+            );
+        }
         Ok(back)
     }
 
@@ -105,47 +394,420 @@ impl TemplateComposite {
     }
 }
 
+/// the variables of a layer
+#[derive(Debug, Clone)]
+pub(crate) struct LayerVariableCfgs {
+    /// namespace where the values are stored
+    pub namespace: Vec<String>,
+    /// order of the layer that imports this layer
+    pub parent: Option<usize>,
+    /// values provided by the import (not asked)
+    pub imported_values: BTreeMap<String, String>,
+    pub variables: Vec<VariableCfg>,
+    /// engine to render the variables
+    pub engine: EngineKind,
+    /// engine to render the values provided by the import (the one of the importing layer)
+    pub parent_engine: EngineKind,
+}
+
+impl LayerVariableCfgs {
+    /// the variables to render the layer: the variables with the values of its namespace,
+    /// and the values provided by the import (rendered with the variables of the importing layer)
+    pub fn scope(&self, variables: &Variables, scopes: &[Variables]) -> Result<Variables> {
+        let mut back = variables.scoped(&self.namespace);
+        if !self.imported_values.is_empty() {
+            let parent_scope = self.parent.and_then(|i| scopes.get(i)).unwrap_or(variables);
+            let engines = Engines::default();
+            for (name, template) in &self.imported_values {
+                let value = engines.get(self.parent_engine).render(
+                    template,
+                    parent_scope,
+                    &format!("define value of '{}' for the import", name),
+                )?;
+                back.insert(name, Variables::value_from_str(&value)?)?;
+            }
+        }
+        Ok(back)
+    }
+
+    /// the value is provided by the import or already defined into the namespace
+    pub fn is_defined(&self, variables: &Variables, name: &str) -> bool {
+        self.imported_values.contains_key(name) || variables.contains_key_in(&self.namespace, name)
+    }
+}
+
+/// the decisions about the conditional imports (by importing layer and index of the import):
+/// the template to import, or None if the import is skipped
+type Conditionals = HashMap<(LayerKey, usize), Option<SourceLoc>>;
+
+/// the downloaded templates, and what is needed to download the conditional imports
+#[derive(Debug, Clone)]
+struct Imports {
+    /// the variables to render the imports (before the variables are asked)
+    variables: Variables,
+    offline: bool,
+    git_opts: GitOpts,
+    import_conflict: ImportConflictPolicy,
+    root: LayerKey,
+    templates: HashMap<SourceLoc, TemplateCfg>,
+    conditionals: Conditionals,
+}
+
+impl Imports {
+    fn find_layers(&self) -> Result<Vec<TemplateLayer>> {
+        let imports = resolve_conflicts(
+            &self.templates,
+            &self.conditionals,
+            &self.root,
+            &self.import_conflict,
+        )?;
+        let paths = imports.find_paths_ordered_by_depth(&self.root);
+        let layers = paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let k = path.last().expect("path should not be empty");
+                let parent = path
+                    .len()
+                    .checked_sub(2)
+                    .and_then(|p| paths.iter().position(|x| x.last() == path.get(p)));
+                TemplateLayer {
+                    order: i,
+                    loc: k.loc.clone(),
+                    cfg: self.templates.get(&k.loc).expect("should exist").clone(),
+                    namespace: k.namespace.clone(),
+                    parent,
+                    imported_values: k.imported_values.clone(),
+                    destination: k.destination.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(layers)
+    }
+}
+
+/// a template imported with a namespace, values for its variables and a destination
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct LayerKey {
+    loc: SourceLoc,
+    namespace: Vec<String>,
+    imported_values: BTreeMap<String, String>,
+    destination: Option<String>,
+}
+
+impl LayerKey {
+    fn root(loc: &SourceLoc) -> LayerKey {
+        LayerKey {
+            loc: loc.clone(),
+            namespace: vec![],
+            imported_values: BTreeMap::new(),
+            destination: None,
+        }
+    }
+}
+
 impl Graph for HashMap<SourceLoc, TemplateCfg> {
     type K = SourceLoc;
     type V = TemplateCfg;
     fn find_node(&self, k: &Self::K) -> Option<&Self::V> {
         self.get(k)
     }
-    fn find_edges_direct(&self, v: &Self::V) -> Vec<Self::K> {
+    fn find_edges_direct(&self, _k: &Self::K, v: &Self::V) -> Vec<Self::K> {
         v.find_sourcelocs()
             .expect("TODO find_sourcelocs without error")
     }
 }
 
+/// the graph of imports, where the conflicting imports are replaced by the kept one
+struct ResolvedImports<'a> {
+    templates: &'a HashMap<SourceLoc, TemplateCfg>,
+    conditionals: &'a Conditionals,
+    replacements: HashMap<SourceLoc, SourceLoc>,
+}
+
+impl<'a> ResolvedImports<'a> {
+    fn replace(&self, k: SourceLoc) -> SourceLoc {
+        let mut k = k;
+        while let Some(v) = self.replacements.get(&k) {
+            k = v.clone();
+        }
+        k
+    }
+}
+
+impl<'a> Graph for ResolvedImports<'a> {
+    type K = LayerKey;
+    type V = TemplateCfg;
+    fn find_node(&self, k: &Self::K) -> Option<&Self::V> {
+        self.templates.find_node(&k.loc)
+    }
+    fn find_edges_direct(&self, k: &Self::K, v: &Self::V) -> Vec<Self::K> {
+        let mut locs = self.templates.find_edges_direct(&k.loc, v).into_iter();
+        v.imports
+            .iter()
+            .enumerate()
+            .filter_map(|(i, import)| {
+                let loc = if import.when.is_none() {
+                    locs.next()
+                } else {
+                    self.conditionals.get(&(k.clone(), i)).cloned().flatten()
+                }?;
+                let mut namespace = k.namespace.clone();
+                namespace.extend(import.namespace.iter().cloned());
+                Some(LayerKey {
+                    loc: self.replace(loc),
+                    namespace,
+                    imported_values: import.variables.clone(),
+                    destination: import.destination.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// same template (uri) and the files of one could be provided by the other
+fn is_conflict(a: &SourceLoc, b: &SourceLoc) -> bool {
+    let overlap = match (&a.subfolder, &b.subfolder) {
+        (Some(sa), Some(sb)) => sa.starts_with(sb) || sb.starts_with(sa),
+        _ => true,
+    };
+    a != b && a.uri.host == b.uri.host && a.uri.path == b.uri.path && overlap
+}
+
+/// the graph of the templates (without namespaces) where the conflicting imports are replaced
+struct ResolvedLocs<'a, 'b>(&'b ResolvedImports<'a>);
+
+impl<'a, 'b> Graph for ResolvedLocs<'a, 'b> {
+    type K = SourceLoc;
+    type V = TemplateCfg;
+    fn find_node(&self, k: &Self::K) -> Option<&Self::V> {
+        self.0.templates.find_node(k)
+    }
+    fn find_edges_direct(&self, k: &Self::K, v: &Self::V) -> Vec<Self::K> {
+        let conditionals = self
+            .0
+            .conditionals
+            .iter()
+            .filter(|((parent, _), _)| &parent.loc == k)
+            .filter_map(|(_, loc)| loc.clone());
+        self.0
+            .templates
+            .find_edges_direct(k, v)
+            .into_iter()
+            .chain(conditionals)
+            .map(|k| self.0.replace(k))
+            .collect()
+    }
+}
+
+fn format_chain(chain: &[LayerKey]) -> String {
+    chain
+        .iter()
+        .map(|l| l.loc.to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// compare revisions as versions: sequences of digits are compared as numbers (eg: v1.10.0 > v1.9.2)
+fn compare_revs(a: &str, b: &str) -> CmpOrdering {
+    fn chunks(s: &str) -> Vec<&str> {
+        let mut back = vec![];
+        let mut start = 0;
+        let mut prev_is_digit = None;
+        for (i, c) in s.char_indices() {
+            let is_digit = c.is_ascii_digit();
+            if prev_is_digit.map(|p| p != is_digit).unwrap_or(false) {
+                back.push(&s[start..i]);
+                start = i;
+            }
+            prev_is_digit = Some(is_digit);
+        }
+        if start < s.len() {
+            back.push(&s[start..]);
+        }
+        back
+    }
+    let (ca, cb) = (chunks(a), chunks(b));
+    for (x, y) in ca.iter().zip(cb.iter()) {
+        let r = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(nx), Ok(ny)) => nx.cmp(&ny),
+            _ => x.cmp(y),
+        };
+        if r != CmpOrdering::Equal {
+            return r;
+        }
+    }
+    ca.len().cmp(&cb.len())
+}
+
+/// detect the conflicting imports and resolve them according to the policy,
+/// the root template is never replaced
+fn resolve_conflicts<'a>(
+    templates: &'a HashMap<SourceLoc, TemplateCfg>,
+    conditionals: &'a Conditionals,
+    root: &LayerKey,
+    policy: &ImportConflictPolicy,
+) -> Result<ResolvedImports<'a>> {
+    let mut imports = ResolvedImports {
+        templates,
+        conditionals,
+        replacements: HashMap::new(),
+    };
+    loop {
+        // check before the traversal by layers (infinite if a cycle includes a namespace)
+        if let Some(cycle) = ResolvedLocs(&imports).find_cycle(&root.loc) {
+            return Err(Error::ImportCycle {
+                chain: cycle
+                    .iter()
+                    .map(|l| l.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> "),
+            });
+        }
+        let paths = imports.find_paths_ordered_by_depth(root);
+        let conflict = paths.iter().enumerate().find_map(|(i, first)| {
+            paths[(i + 1)..]
+                .iter()
+                .find(|second| {
+                    is_conflict(&first[first.len() - 1].loc, &second[second.len() - 1].loc)
+                })
+                .map(|second| (first, second))
+        });
+        let (first, second) = match conflict {
+            None => return Ok(imports),
+            Some(v) => v,
+        };
+        let first_loc = &first[first.len() - 1].loc;
+        let second_loc = &second[second.len() - 1].loc;
+        let (kept, replaced) = match policy {
+            ImportConflictPolicy::Error => {
+                return Err(Error::ImportConflict {
+                    uri: first_loc.uri.raw.clone(),
+                    first: format_chain(first),
+                    second: format_chain(second),
+                })
+            }
+            ImportConflictPolicy::PreferHighest
+                if first.len() > 1
+                    && compare_revs(&second_loc.rev, &first_loc.rev) == CmpOrdering::Greater =>
+            {
+                (second, first)
+            }
+            _ => (first, second),
+        };
+        warn!(
+            kept = format_chain(kept),
+            replaced = format_chain(replaced),
+            "conflicting imports"
+        );
+        imports.replacements.insert(
+            replaced[replaced.len() - 1].loc.clone(),
+            kept[kept.len() - 1].loc.clone(),
+        );
+    }
+}
+
+/// maximum number of templates downloaded in parallel
+const DOWNLOAD_WORKERS_MAX: usize = 4;
+
+/// the configuration of a downloaded template, and its imports (with the variables to render them)
+type Downloaded = (TemplateCfg, Vec<(Variables, SourceLoc)>);
+
 //struct Template;
-#[instrument(skip(variables, templates))]
+/// download the template and its imports (recursively),
+/// the imports of a same depth are downloaded in parallel
+#[instrument(skip(variables, git_opts, templates))]
 fn deep_download(
     variables: &Variables,
     offline: bool,
+    git_opts: &GitOpts,
     src: &SourceLoc,
     templates: &mut HashMap<SourceLoc, TemplateCfg>,
 ) -> Result<()> {
-    if !templates.contains_key(src) {
-        let template_base_path = &src.download(offline)?;
-        // update cfg with variables defined by user
-        let template_cfg = TemplateCfg::from_template_folder(template_base_path)?;
-        // update cfg with variables defined by cli (use to update default_value)
-        let mut variables_children = variables.clone();
-        variables_children.insert("ffizer_src_uri", src.uri.raw.clone())?;
-        variables_children.insert("ffizer_src_rev", src.rev.clone())?;
-        //variables_children.insert("ffizer_src_subfolder".to_owned(), src.subfolder.clone());
-        let template_cfg_for_imports =
-            render_imports_only(&template_cfg, &variables_children, false)?;
-        let children = template_cfg_for_imports.find_sourcelocs()?;
-        //WARN: Do insert a rendered templates because the value of are not yet defined
-        templates.insert(src.clone(), template_cfg_for_imports);
-        for child in children {
-            deep_download(&variables_children, offline, &child, templates)?;
+    let mut level = vec![(variables.clone(), src.clone())];
+    while !level.is_empty() {
+        let mut todo: Vec<(Variables, SourceLoc)> = Vec::with_capacity(level.len());
+        for (variables, loc) in level {
+            if !templates.contains_key(&loc) && !todo.iter().any(|(_, l)| l == &loc) {
+                todo.push((variables, loc));
+            }
+        }
+        let mut next_level = vec![];
+        for ((_, loc), downloaded) in todo.iter().zip(download_all(&todo, offline, git_opts)) {
+            let (template_cfg, children) = downloaded?;
+            templates.insert(loc.clone(), template_cfg);
+            next_level.extend(children);
         }
+        level = next_level;
     }
     Ok(())
 }
 
+/// download the templates with a bounded pool of workers,
+/// the results are in the same order than the input
+fn download_all(
+    todo: &[(Variables, SourceLoc)],
+    offline: bool,
+    git_opts: &GitOpts,
+) -> Vec<Result<Downloaded>> {
+    let nb_workers = todo.len().min(DOWNLOAD_WORKERS_MAX);
+    if nb_workers <= 1 {
+        return todo
+            .iter()
+            .map(|(variables, loc)| download_one(variables, offline, git_opts, loc))
+            .collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results = todo.iter().map(|_| Mutex::new(None)).collect::<Vec<_>>();
+    std::thread::scope(|scope| {
+        for _ in 0..nb_workers {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if let Some((variables, loc)) = todo.get(i) {
+                    let r = download_one(variables, offline, git_opts, loc);
+                    *results[i].lock().expect("lock result of download") = Some(r);
+                } else {
+                    break;
+                }
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|r| {
+            r.into_inner()
+                .expect("lock result of download")
+                .expect("every template to be processed")
+        })
+        .collect()
+}
+
+/// download a template, and provide its imports (with the variables to render their configuration)
+#[instrument(skip(variables, git_opts))]
+fn download_one(
+    variables: &Variables,
+    offline: bool,
+    git_opts: &GitOpts,
+    src: &SourceLoc,
+) -> Result<Downloaded> {
+    let template_base_path = &src.download(offline, git_opts)?;
+    // update cfg with variables defined by user
+    let template_cfg = TemplateCfg::from_template_folder(template_base_path)?;
+    // update cfg with variables defined by cli (use to update default_value)
+    let mut variables_children = variables.clone();
+    variables_children.insert("ffizer_src_uri", src.uri.raw.clone())?;
+    variables_children.insert("ffizer_src_rev", src.rev.clone())?;
+    //variables_children.insert("ffizer_src_subfolder".to_owned(), src.subfolder.clone());
+    let template_cfg_for_imports = render_imports_only(&template_cfg, &variables_children, false)?;
+    let children = template_cfg_for_imports
+        .find_sourcelocs()?
+        .into_iter()
+        .map(|child| (variables_children.clone(), child))
+        .collect();
+    //WARN: Do insert a rendered templates because the value of are not yet defined
+    Ok((template_cfg_for_imports, children))
+}
+
 impl TransformsValues for TemplateComposite {
     /// transforms ignore, imports
     fn transforms_values<F>(&self, render: &F) -> Result<Self>
@@ -153,29 +815,33 @@ impl TransformsValues for TemplateComposite {
         F: Fn(&str) -> String,
     {
         let layers = self.layers.transforms_values(render)?;
-        Ok(TemplateComposite { layers })
+        Ok(TemplateComposite {
+            layers,
+            imports: self.imports.clone(),
+        })
     }
 }
 
+/// render the configuration of each layer with its variables (see `find_layers_variables`)
 pub(crate) fn render_composite(
     template_composite: &TemplateComposite,
-    variables: &Variables,
+    layers_variables: &[Variables],
     log_warning: bool,
 ) -> Result<TemplateComposite> {
-    let handlebars = new_hbs();
-    let render = |v: &str| {
-        let r = handlebars.render_template(v, variables);
-        match r {
-            Ok(s) => s,
-            Err(e) => {
-                if log_warning {
-                    warn!(input = ?v, error = ?e, "failed to convert")
-                }
-                v.into()
-            }
-        }
-    };
-    template_composite.transforms_values(&render)
+    let engines = Engines::default();
+    let layers = template_composite
+        .layers
+        .iter()
+        .zip(layers_variables)
+        .map(|(layer, variables)| {
+            let render = value_renderer(engines.get(layer.cfg.engine), variables, log_warning);
+            layer.transforms_values(&render)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(TemplateComposite {
+        layers,
+        imports: template_composite.imports.clone(),
+    })
 }
 
 fn render_imports_only(
@@ -183,28 +849,317 @@ fn render_imports_only(
     variables: &Variables,
     log_warning: bool,
 ) -> Result<TemplateCfg> {
-    let handlebars = new_hbs();
-    let render = |v: &str| {
-        let r = handlebars.render_template(v, variables);
-        match r {
-            Ok(s) => s,
-            Err(e) => {
-                if log_warning {
-                    warn!(input = ?v, error = ?e, "failed to convert")
-                }
-                v.into()
-            }
-        }
-    };
+    let engines = Engines::default();
+    let render = value_renderer(engines.get(template_cfg.engine), variables, log_warning);
     let variables = template_cfg.variables.clone();
     let ignores = template_cfg.ignores.clone();
-    let imports = template_cfg.imports.transforms_values(&render)?;
+    // the conditional imports are rendered after the variables are asked
+    let imports = template_cfg
+        .imports
+        .iter()
+        .map(|v| {
+            if v.when.is_some() {
+                Ok(v.clone())
+            } else {
+                v.transforms_values(&render)
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
     let scripts = template_cfg.scripts.clone();
     Ok(TemplateCfg {
         variables,
         ignores,
+        render: template_cfg.render.clone(),
+        raw: template_cfg.raw.clone(),
+        engine: template_cfg.engine,
+        directories: template_cfg.directories.clone(),
+        files: template_cfg.files.clone(),
+        eol: template_cfg.eol,
+        helpers: template_cfg.helpers.clone(),
+        formatters: template_cfg.formatters.clone(),
         imports,
+        patches: template_cfg.patches.clone(),
+        removed: template_cfg.removed.clone(),
+        renamed: template_cfg.renamed.clone(),
         scripts,
         use_template_dir: template_cfg.use_template_dir,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_from_src_keep_layers_ordered_by_depth() {
+        let src = SourceLoc {
+            uri: crate::SourceUri::from_str("tests/data/template_4_compose").unwrap(),
+            rev: "master".to_owned(),
+            ..Default::default()
+        };
+        let variables = Variables::default();
+        let composite = TemplateComposite::from_src(
+            &variables,
+            true,
+            &GitOpts::default(),
+            &ImportConflictPolicy::default(),
+            &src,
+        )
+        .expect("composite template");
+        let actual = composite
+            .layers
+            .iter()
+            .map(|l| {
+                (
+                    l.order,
+                    l.loc.uri.raw.clone(),
+                    l.loc
+                        .subfolder
+                        .as_ref()
+                        .map(|s| s.to_string_lossy().to_string()),
+                )
+            })
+            .collect::<Vec<_>>();
+        let expected = vec![
+            (0, "tests/data/template_4_compose".to_owned(), None),
+            (
+                1,
+                "tests/data/template_4_compose/../4compose".to_owned(),
+                Some("template_1".to_owned()),
+            ),
+            (
+                2,
+                "tests/data/template_4_compose/../4compose/template_2".to_owned(),
+                None,
+            ),
+            (
+                3,
+                "tests/data/template_4_compose/../4compose".to_owned(),
+                Some("template_1_1".to_owned()),
+            ),
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    fn new_loc(uri: &str, rev: &str, subfolder: Option<&str>) -> SourceLoc {
+        SourceLoc {
+            uri: crate::SourceUri::from_str(uri).unwrap(),
+            rev: rev.to_owned(),
+            subfolder: subfolder.map(std::path::PathBuf::from),
+            ..Default::default()
+        }
+    }
+
+    fn new_cfg(imports: &[&SourceLoc]) -> TemplateCfg {
+        TemplateCfg {
+            imports: imports
+                .iter()
+                .map(|l| super::super::import_cfg::ImportCfg {
+                    uri: l.uri.raw.clone(),
+                    rev: Some(l.rev.clone()),
+                    subfolder: l
+                        .subfolder
+                        .as_ref()
+                        .map(|s| s.to_string_lossy().to_string()),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// root -> lib (v1.2), root -> other -> lib (v1.10)
+    fn new_diamond() -> (
+        SourceLoc,
+        SourceLoc,
+        SourceLoc,
+        HashMap<SourceLoc, TemplateCfg>,
+    ) {
+        let root = new_loc("git@github.com:ffizer/root.git", "master", None);
+        let lib_nearest = new_loc("git@github.com:ffizer/lib.git", "v1.2", Some("base"));
+        let lib_highest = new_loc("git@github.com:ffizer/lib.git", "v1.10", None);
+        let other = new_loc("git@github.com:ffizer/other.git", "master", None);
+        let mut templates = HashMap::new();
+        templates.insert(root.clone(), new_cfg(&[&lib_nearest, &other]));
+        templates.insert(lib_nearest.clone(), new_cfg(&[]));
+        templates.insert(other.clone(), new_cfg(&[&lib_highest]));
+        templates.insert(lib_highest.clone(), new_cfg(&[]));
+        (root, lib_nearest, lib_highest, templates)
+    }
+
+    #[test]
+    fn test_resolve_conflicts_should_report_import_chains() {
+        let (root, _, _, templates) = new_diamond();
+        let actual = resolve_conflicts(
+            &templates,
+            &HashMap::new(),
+            &LayerKey::root(&root),
+            &ImportConflictPolicy::Error,
+        )
+        .err()
+        .expect("conflict error")
+        .to_string();
+        assert_eq!(
+            actual,
+            "imports of \"git@github.com:ffizer/lib.git\" conflict (different revisions or overlapping subfolders), use `--import-conflict` to choose one:
+  git@github.com:ffizer/root.git (rev: master) -> git@github.com:ffizer/lib.git (rev: v1.2, subfolder: base)
+  git@github.com:ffizer/root.git (rev: master) -> git@github.com:ffizer/other.git (rev: master) -> git@github.com:ffizer/lib.git (rev: v1.10)"
+        );
+    }
+
+    #[test]
+    fn test_resolve_conflicts_with_policy() {
+        let (root, lib_nearest, lib_highest, templates) = new_diamond();
+        let other = new_loc("git@github.com:ffizer/other.git", "master", None);
+        let root_key = LayerKey::root(&root);
+        let find_locs = |policy: &ImportConflictPolicy| {
+            resolve_conflicts(&templates, &HashMap::new(), &root_key, policy)
+                .unwrap()
+                .find_paths_ordered_by_depth(&root_key)
+                .into_iter()
+                .filter_map(|mut path| path.pop())
+                .map(|k| k.loc)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            find_locs(&ImportConflictPolicy::PreferNearest),
+            vec![root.clone(), lib_nearest, other.clone()]
+        );
+        assert_eq!(
+            find_locs(&ImportConflictPolicy::PreferHighest),
+            vec![root, lib_highest, other]
+        );
+    }
+
+    #[test]
+    fn test_resolve_conflicts_should_report_cycle() {
+        let root = new_loc("git@github.com:ffizer/root.git", "master", None);
+        let other = new_loc("git@github.com:ffizer/other.git", "master", None);
+        let mut templates = HashMap::new();
+        templates.insert(root.clone(), new_cfg(&[&other]));
+        templates.insert(other, new_cfg(&[&root]));
+        let actual = resolve_conflicts(
+            &templates,
+            &HashMap::new(),
+            &LayerKey::root(&root),
+            &ImportConflictPolicy::Error,
+        )
+        .err()
+        .expect("cycle error")
+        .to_string();
+        assert_eq!(
+            actual,
+            "cycle of imports: git@github.com:ffizer/root.git (rev: master) -> git@github.com:ffizer/other.git (rev: master) -> git@github.com:ffizer/root.git (rev: master)"
+        );
+    }
+
+    #[test]
+    fn test_import_same_template_with_namespaces() {
+        let root = new_loc("git@github.com:ffizer/root.git", "master", None);
+        let svc = new_loc("git@github.com:ffizer/svc.git", "master", None);
+        let mut root_cfg = new_cfg(&[&svc, &svc]);
+        root_cfg.imports[0].namespace = Some("svc_a".to_owned());
+        root_cfg.imports[0]
+            .variables
+            .insert("port".to_owned(), "{{ base_port }}1".to_owned());
+        root_cfg.imports[1].namespace = Some("svc_b".to_owned());
+        let mut templates = HashMap::new();
+        templates.insert(root.clone(), root_cfg);
+        templates.insert(svc, new_cfg(&[]));
+        let root_key = LayerKey::root(&root);
+        let paths = resolve_conflicts(
+            &templates,
+            &HashMap::new(),
+            &root_key,
+            &ImportConflictPolicy::Error,
+        )
+        .unwrap()
+        .find_paths_ordered_by_depth(&root_key);
+        let actual = paths
+            .iter()
+            .map(|p| p[p.len() - 1].namespace.join("."))
+            .collect::<Vec<_>>();
+        assert_eq!(actual, vec!["", "svc_a", "svc_b"]);
+
+        let mut variables = Variables::default();
+        variables.insert("base_port", 808).unwrap();
+        variables.insert("port", 80).unwrap();
+        let layer = LayerVariableCfgs {
+            namespace: vec!["svc_a".to_owned()],
+            parent: Some(0),
+            imported_values: paths[1][1].imported_values.clone(),
+            variables: vec![],
+            engine: EngineKind::default(),
+            parent_engine: EngineKind::default(),
+        };
+        let scope = layer.scope(&variables, &[variables.clone()]).unwrap();
+        assert_eq!(
+            serde_yaml::to_value(&scope).unwrap()["port"],
+            serde_yaml::to_value(8081).unwrap()
+        );
+        assert!(layer.is_defined(&variables, "port"));
+        assert!(!layer.is_defined(&variables, "base_port"));
+    }
+
+    #[test]
+    fn test_find_layers_destinations() {
+        let src = SourceLoc {
+            uri: crate::SourceUri::from_str("tests/data/template_13_import_destination").unwrap(),
+            rev: "master".to_owned(),
+            ..Default::default()
+        };
+        let mut variables = Variables::default();
+        variables.insert("front_name", "web").unwrap();
+        let mut composite = TemplateComposite::from_src(
+            &variables,
+            true,
+            &GitOpts::default(),
+            &ImportConflictPolicy::default(),
+            &src,
+        )
+        .expect("composite template");
+        let layers_variables = vec![variables; composite.layers.len()];
+        assert_eq!(
+            composite
+                .find_layers_destinations(&layers_variables)
+                .unwrap(),
+            vec![
+                PathBuf::new(),
+                PathBuf::from("services/api"),
+                PathBuf::from("services/web"),
+                PathBuf::from("tools/generated")
+            ]
+        );
+
+        composite.layers[1].destination = Some("../outside".to_owned());
+        assert!(matches!(
+            composite.find_layers_destinations(&layers_variables),
+            Err(Error::ImportDestination { .. })
+        ));
+    }
+
+    #[test]
+    fn test_is_conflict() {
+        let lib = new_loc("tests/data/lib", "master", None);
+        let lib_a = new_loc("tests/data/lib", "master", Some("a"));
+        let lib_a_b = new_loc("tests/data/lib", "master", Some("a/b"));
+        let lib_ab = new_loc("tests/data/lib", "master", Some("ab"));
+        assert!(!is_conflict(&lib_a, &lib_a));
+        assert!(is_conflict(&lib, &lib_a));
+        assert!(is_conflict(&lib_a_b, &lib_a));
+        assert!(!is_conflict(&lib_ab, &lib_a));
+        assert!(!is_conflict(
+            &lib_a,
+            &new_loc("tests/data/other", "master", None)
+        ));
+    }
+
+    #[test]
+    fn test_compare_revs() {
+        assert_eq!(compare_revs("v1.10.0", "v1.9.2"), CmpOrdering::Greater);
+        assert_eq!(compare_revs("1.0", "1.0.1"), CmpOrdering::Less);
+        assert_eq!(compare_revs("master", "master"), CmpOrdering::Equal);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::source_loc::SourceLoc;
+use crate::user_cfg::UserCfg;
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
@@ -55,6 +56,23 @@ pub struct ApplyOpts {
     #[arg(long = "offline")]
     pub offline: bool,
 
+    #[command(flatten)]
+    pub git: GitOpts,
+
+    /// policy to resolve imports of a same template with different revisions or overlapping subfolders
+    #[arg(long, default_value = "Error", value_enum, ignore_case = true)]
+    pub import_conflict: ImportConflictPolicy,
+
+    /// policy for the symlinks of the template
+    #[arg(long, default_value = "Preserve", value_enum, ignore_case = true)]
+    pub symlinks: SymlinkPolicy,
+
+    /// record the generated files (into `.ffizer.record.yaml` of the destination),
+    /// to delete the files no longer provided by the template on the next apply
+    /// (always enabled when the destination has a record)
+    #[arg(long = "record")]
+    pub record: bool,
+
     #[command(flatten)]
     pub src: SourceLoc,
 
@@ -121,6 +139,28 @@ impl std::fmt::Display for UpdateMode {
     }
 }
 
+#[derive(Debug, Default, Clone, PartialEq, Eq, ValueEnum)]
+/// policy to resolve conflicting imports (same template with different revisions or overlapping subfolders)
+pub enum ImportConflictPolicy {
+    // fail and report the import chains of the conflicting imports
+    #[default]
+    Error,
+    // keep the import nearest to the root template (first in the order of layers)
+    PreferNearest,
+    // keep the import with the highest revision (compared as version, eg: v1.10.0 > v1.9.2)
+    PreferHighest,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// policy for the symlinks of the template
+pub enum SymlinkPolicy {
+    // create symlinks (their targets are rendered)
+    #[default]
+    Preserve,
+    // copy / render the files & folders targeted by the symlinks
+    Follow,
+}
+
 fn parse_keyvalue(src: &str) -> Result<(String, String), String> {
     let kv: Vec<&str> = src.splitn(2, '=').collect();
     if kv.len() == 2 {
@@ -137,6 +177,37 @@ pub struct TestSamplesOpts {
     /// in offline, only local templates or cached templates are used
     #[arg(long = "offline")]
     pub offline: bool,
+    #[command(flatten)]
+    pub git: GitOpts,
+    /// policy to resolve imports of a same template with different revisions or overlapping subfolders
+    /// (for the samples that don't define it into their `apply_args`)
+    #[arg(long, default_value = "Error", value_enum, ignore_case = true)]
+    pub import_conflict: ImportConflictPolicy,
+    /// policy for the symlinks of the template
+    /// (for the samples that don't define it into their `apply_args`)
+    #[arg(long, default_value = "Preserve", value_enum, ignore_case = true)]
+    pub symlinks: SymlinkPolicy,
+    /// record the generated files of the samples (into `.ffizer.record.yaml`)
+    #[arg(long = "record")]
+    pub record: bool,
+}
+
+/// options to access remote (git) templates
+/// for https remotes, a token can be provided via the environment variable `FFIZER_GIT_TOKEN`
+/// settings by host can be defined into the user configuration (see `ffizer inspect`)
+#[derive(Args, Debug, Default, Clone, PartialEq, Eq)]
+pub struct GitOpts {
+    /// private ssh key to use to retrieve remote templates (override the one defined for the host)
+    #[arg(long = "ssh-key", value_name = "FILE")]
+    pub ssh_key: Option<PathBuf>,
+
+    /// if remote templates can not be retrieved (eg: network failure), use the cached version (if available)
+    #[arg(long = "prefer-offline", conflicts_with = "offline")]
+    pub prefer_offline: bool,
+
+    /// configuration of the user (settings by host), loaded once by `process`
+    #[arg(skip)]
+    pub user_cfg: UserCfg,
 }
 
 #[cfg(test)]
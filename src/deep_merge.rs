@@ -0,0 +1,155 @@
+//! merge of structured files (json, yaml, toml):
+//! maps are merged recursively, arrays are concatenated (without duplicates),
+//! for other values the one of `over` is kept.
+use crate::error::*;
+use serde_yaml::Value;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Format> {
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("json") => Some(Format::Json),
+            Some("yaml") | Some("yml") => Some(Format::Yaml),
+            Some("toml") => Some(Format::Toml),
+            _ => None,
+        }
+    }
+
+    fn parse(&self, content: &[u8]) -> std::result::Result<Value, String> {
+        let content = String::from_utf8_lossy(content);
+        if content.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        match self {
+            Format::Json => serde_json::from_str(&content).map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::from_str(&content).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_str(&content).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn serialize(&self, value: &Value) -> std::result::Result<Vec<u8>, String> {
+        let s = match self {
+            Format::Json => serde_json::to_string_pretty(value)
+                .map(|s| s + "\n")
+                .map_err(|e| e.to_string())?,
+            Format::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string())?,
+            Format::Toml => toml::Value::try_from(value)
+                .and_then(|v| toml::to_string(&v))
+                .map_err(|e| e.to_string())?,
+        };
+        Ok(s.into_bytes())
+    }
+}
+
+/// merge `over` into `base` (the format is defined by the extension of `path`)
+pub(crate) fn merge(path: &Path, base: &[u8], over: &[u8]) -> Result<Vec<u8>> {
+    let to_error = |msg: String| Error::DeepMerge {
+        path: path.to_path_buf(),
+        msg,
+    };
+    let format = Format::from_path(path).ok_or_else(|| to_error("unknown format".to_owned()))?;
+    let base = format.parse(base).map_err(to_error)?;
+    let over = format.parse(over).map_err(to_error)?;
+    format
+        .serialize(&merge_values(base, over))
+        .map_err(to_error)
+}
+
+fn merge_values(base: Value, over: Value) -> Value {
+    match (base, over) {
+        (Value::Mapping(mut base), Value::Mapping(over)) => {
+            for (k, v) in over {
+                if let Some(b) = base.get_mut(&k) {
+                    *b = merge_values(std::mem::replace(b, Value::Null), v);
+                } else {
+                    base.insert(k, v);
+                }
+            }
+            Value::Mapping(base)
+        }
+        (Value::Sequence(mut base), Value::Sequence(over)) => {
+            for v in over {
+                if !base.contains(&v) {
+                    base.push(v);
+                }
+            }
+            Value::Sequence(base)
+        }
+        (base, Value::Null) => base,
+        (_, over) => over,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn merge_str(path: &str, base: &str, over: &str) -> String {
+        String::from_utf8(merge(Path::new(path), base.as_bytes(), over.as_bytes()).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_merge_json() {
+        let actual = merge_str(
+            "package.json",
+            r#"{"name": "app", "version": "1.0.0", "dependencies": {"a": "1"}, "files": ["lib"]}"#,
+            r#"{"version": "2.0.0", "dependencies": {"b": "2"}, "files": ["lib", "bin"]}"#,
+        );
+        assert_eq!(
+            actual,
+            r#"{
+  "name": "app",
+  "version": "2.0.0",
+  "dependencies": {
+    "a": "1",
+    "b": "2"
+  },
+  "files": [
+    "lib",
+    "bin"
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_merge_yaml() {
+        let actual = merge_str(
+            "ci.yml",
+            "on: [push]\njobs:\n  build:\n    runs-on: ubuntu-latest\n",
+            "on: [pull_request]\njobs:\n  lint:\n    runs-on: ubuntu-latest\n",
+        );
+        assert_eq!(
+            actual,
+            "on:\n- push\n- pull_request\njobs:\n  build:\n    runs-on: ubuntu-latest\n  lint:\n    runs-on: ubuntu-latest\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_toml() {
+        let actual = merge_str(
+            "Cargo.toml",
+            "[package]\nname = \"app\"\n\n[dependencies]\nserde = \"1\"\n",
+            "[dependencies]\ntracing = \"0.1\"\n",
+        );
+        assert_eq!(
+            actual,
+            "[package]\nname = \"app\"\n\n[dependencies]\nserde = \"1\"\ntracing = \"0.1\"\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_unknown_format() {
+        assert!(merge(Path::new("README.md"), b"a", b"b").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! the template engines: handlebars (default) and a jinja-compatible engine (minijinja),
+//! selected by template (`engine` of `.ffizer.yaml`) or by file (suffix `.ffizer.hbs` or `.ffizer.j2`).
+use crate::error::*;
+use crate::files;
+use crate::variables::Variables;
+use handlebars::Handlebars;
+use handlebars_misc_helpers::new_hbs;
+use schemars::JsonSchema;
+use std::path::Path;
+use tracing::warn;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineKind {
+    /// handlebars, with the helpers of `handlebars_misc_helpers`
+    #[default]
+    Handlebars,
+    /// jinja-compatible (minijinja), with its builtin filters and tests
+    Jinja,
+}
+
+impl EngineKind {
+    /// the engine selected by the suffix of the file, None if the file has no suffix of engine
+    pub fn from_path(path: &Path) -> Option<EngineKind> {
+        if files::is_ffizer_jinja(path) {
+            Some(EngineKind::Jinja)
+        } else if files::is_ffizer_handlebars(path) {
+            Some(EngineKind::Handlebars)
+        } else {
+            None
+        }
+    }
+
+    /// the suffix of the files rendered by the engine
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            EngineKind::Handlebars => files::FILEEXT_HANDLEBARS,
+            EngineKind::Jinja => files::FILEEXT_JINJA,
+        }
+    }
+}
+
+/// render a template (the content of a file, a path, a value of the configuration) with the variables
+pub(crate) trait Engine {
+    /// `name` identifies the template into the errors (eg: the path of the file),
+    /// `when` describes the rendering (into the errors)
+    fn render_named(
+        &self,
+        name: &str,
+        template: &str,
+        variables: &Variables,
+        when: &str,
+    ) -> Result<String>;
+
+    /// render a value (the template is its own name into the errors)
+    fn render(&self, template: &str, variables: &Variables, when: &str) -> Result<String> {
+        self.render_named(template, template, variables, when)
+    }
+}
+
+impl Engine for Handlebars<'_> {
+    fn render_named(
+        &self,
+        name: &str,
+        template: &str,
+        variables: &Variables,
+        when: &str,
+    ) -> Result<String> {
+        self.render_template(template, variables)
+            .map_err(|source| Error::Handlebars {
+                when: when.to_owned(),
+                template: name.to_owned(),
+                source,
+            })
+    }
+}
+
+impl Engine for minijinja::Environment<'_> {
+    fn render_named(
+        &self,
+        name: &str,
+        template: &str,
+        variables: &Variables,
+        when: &str,
+    ) -> Result<String> {
+        self.render_named_str(name, template, variables)
+            .map_err(|source| Error::Jinja {
+                when: when.to_owned(),
+                template: name.to_owned(),
+                source,
+            })
+    }
+}
+
+/// the function to render the values of a configuration (see `TransformsValues`),
+/// a value that fails to render is kept as is (and reported if `log_warning`)
+pub(crate) fn value_renderer<'a>(
+    engine: &'a dyn Engine,
+    variables: &'a Variables,
+    log_warning: bool,
+) -> impl Fn(&str) -> String + 'a {
+    move |v: &str| match engine.render(v, variables, "render a value of the configuration") {
+        Ok(s) => s,
+        Err(e) => {
+            if log_warning {
+                warn!(input = ?v, error = ?e, "failed to convert")
+            }
+            v.into()
+        }
+    }
+}
+
+/// the engines (with the helpers and the partials of the template, see `TemplateComposite`)
+pub(crate) struct Engines {
+    pub handlebars: Handlebars<'static>,
+    pub jinja: minijinja::Environment<'static>,
+}
+
+impl Default for Engines {
+    fn default() -> Self {
+        let mut jinja = minijinja::Environment::new();
+        // like handlebars: no escape (the generated files are not only html), keep the last line ending
+        jinja.set_auto_escape_callback(|_| minijinja::AutoEscape::None);
+        jinja.set_keep_trailing_newline(true);
+        Engines {
+            handlebars: new_hbs(),
+            jinja,
+        }
+    }
+}
+
+impl Engines {
+    pub fn get(&self, kind: EngineKind) -> &dyn Engine {
+        match kind {
+            EngineKind::Handlebars => &self.handlebars,
+            EngineKind::Jinja => &self.jinja,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_render_with_each_engine() {
+        let engines = Engines::default();
+        let mut variables = Variables::default();
+        variables.insert("name", "<world>").unwrap();
+        variables.insert("items", vec!["a", "b"]).unwrap();
+        assert_eq!(
+            engines
+                .get(EngineKind::Handlebars)
+                .render(
+                    "{{ name }}{{#each items}} {{ this }}{{/each}}\n",
+                    &variables,
+                    "test"
+                )
+                .unwrap(),
+            "<world> a b\n"
+        );
+        assert_eq!(
+            engines
+                .get(EngineKind::Jinja)
+                .render_named(
+                    "index.html.ffizer.j2",
+                    "{{ name }}{% for item in items %} {{ item }}{% endfor %}\n",
+                    &variables,
+                    "test"
+                )
+                .unwrap(),
+            "<world> a b\n"
+        );
+        assert!(engines
+            .get(EngineKind::Jinja)
+            .render("{% if %}", &variables, "test")
+            .is_err());
+    }
+
+    #[test]
+    fn test_engine_from_path() {
+        assert_eq!(
+            EngineKind::from_path(&PathBuf::from("foo.txt.ffizer.j2")),
+            Some(EngineKind::Jinja)
+        );
+        assert_eq!(
+            EngineKind::from_path(&PathBuf::from("foo.txt.ffizer.hbs")),
+            Some(EngineKind::Handlebars)
+        );
+        assert_eq!(EngineKind::from_path(&PathBuf::from("foo.j2")), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! line endings of the generated text files: defined by the template (`eol` of `.ffizer.yaml`)
+//! or by the `.gitattributes` of the destination.
+use crate::cfg::Eol;
+use crate::error::*;
+use crate::path_pattern::PathPattern;
+use crate::text;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// the line endings defined by the `.gitattributes` of the folder (the last matching pattern wins),
+/// `eol=lf`, `eol=crlf`, `text` (native), `-text` and `binary` (preserve)
+pub(crate) fn find_gitattributes_eols(folder: &Path) -> Result<Vec<(PathPattern, Eol)>> {
+    let path = folder.join(".gitattributes");
+    if !path.is_file() {
+        return Ok(vec![]);
+    }
+    let content = fs::read_to_string(&path).map_err(|source| Error::ReadFile { path, source })?;
+    let mut back = vec![];
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let pattern = match fields.next() {
+            Some(v) => v,
+            None => continue,
+        };
+        let eol = fields.fold(None, |acc, attr| match attr {
+            "eol=lf" => Some(Eol::Lf),
+            "eol=crlf" => Some(Eol::Crlf),
+            "-text" | "binary" => Some(Eol::Preserve),
+            "text" | "text=auto" => acc.or(Some(Eol::Native)),
+            _ => acc,
+        });
+        if let Some(eol) = eol {
+            // a pattern without slash matches at any level
+            let glob = match pattern.strip_prefix('/') {
+                Some(v) => v.to_owned(),
+                None if !pattern.contains('/') => format!("**/{}", pattern),
+                None => pattern.to_owned(),
+            };
+            back.push((PathPattern::from_str(&glob)?, eol));
+        }
+    }
+    back.reverse();
+    Ok(back)
+}
+
+/// the content with the line endings, the binary contents are not modified
+pub(crate) fn convert(content: Vec<u8>, eol: Eol) -> Vec<u8> {
+    let crlf = match eol {
+        Eol::Preserve => return content,
+        Eol::Lf => false,
+        Eol::Crlf => true,
+        Eol::Native => cfg!(windows),
+    };
+    if text::is_binary(&content) {
+        return content;
+    }
+    let (s, encoding) = text::decode(&content);
+    let mut s = s.replace("\r\n", "\n");
+    if crlf {
+        s = s.replace('\n', "\r\n");
+    }
+    text::encode(&s, encoding)
+}
+
+/// the content to compare with an other one: when the line endings are preserved,
+/// the differences of line endings are ignored
+pub(crate) fn comparable(content: Vec<u8>, eol: Eol) -> Vec<u8> {
+    match eol {
+        Eol::Preserve => convert(content, Eol::Lf),
+        _ => content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(convert(b"a\r\nb\nc".to_vec(), Eol::Lf), b"a\nb\nc".to_vec());
+        assert_eq!(
+            convert(b"a\r\nb\nc".to_vec(), Eol::Crlf),
+            b"a\r\nb\r\nc".to_vec()
+        );
+        assert_eq!(
+            convert(b"a\r\nb\nc".to_vec(), Eol::Preserve),
+            b"a\r\nb\nc".to_vec()
+        );
+        assert_eq!(convert(b"\x00\r\n".to_vec(), Eol::Lf), b"\x00\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_find_gitattributes_eols() {
+        let tmp_dir = tempfile::TempDir::new().expect("create a temp dir");
+        fs::write(
+            tmp_dir.path().join(".gitattributes"),
+            "# line endings\n* text=auto\n*.sh text eol=lf\n/scripts/*.bat eol=crlf\n*.png binary\n",
+        )
+        .unwrap();
+        let eols = find_gitattributes_eols(tmp_dir.path()).unwrap();
+        let find = |path: &str| {
+            eols.iter()
+                .find(|(p, _)| p.is_match(path))
+                .map(|(_, eol)| *eol)
+        };
+        assert_eq!(find("src/run.sh"), Some(Eol::Lf));
+        assert_eq!(find("scripts/run.bat"), Some(Eol::Crlf));
+        assert_eq!(find("doc/logo.png"), Some(Eol::Preserve));
+        assert_eq!(find("README.md"), Some(Eol::Native));
+    }
+}
@@ -5,6 +5,7 @@
 // - [Error Handling in Rust - Andrew Gallant's Blog](https://blog.burntsushi.net/rust-error-handling/)
 // use std::backtrace::Backtrace;
 use std::path::PathBuf;
+use std::time::Duration;
 use thiserror::Error;
 use tracing_error::SpanTrace;
 
@@ -31,6 +32,12 @@ pub enum Error {
         source: git2::Error,
         msg: String,
     },
+    #[error("failed to authenticate to {url:?}, provide a ssh key (`--ssh-key`) or a token (env `FFIZER_GIT_TOKEN`), or define them for the host into {user_cfg:?}")]
+    GitAuthentication {
+        url: String,
+        user_cfg: PathBuf,
+        source: git2::Error,
+    },
     #[error("try to find git config '{key:?}'")]
     GitFindConfig { key: String, source: git2::Error },
 
@@ -61,6 +68,12 @@ pub enum Error {
         path: PathBuf,
         source: std::io::Error,
     },
+    #[error("create symlink {path:?} to {target:?}")]
+    CreateSymlink {
+        path: PathBuf,
+        target: PathBuf,
+        source: std::io::Error,
+    },
     #[error("rename file from {src:?} to {dst:?}")]
     RenameFile {
         src: PathBuf,
@@ -79,6 +92,11 @@ pub enum Error {
         dst: PathBuf,
         source: std::io::Error,
     },
+    #[error("set permissions of {path:?}")]
+    SetPermissions {
+        path: PathBuf,
+        source: std::io::Error,
+    },
     #[error("read file {path:?}")]
     ReadFile {
         path: PathBuf,
@@ -89,6 +107,13 @@ pub enum Error {
         path: PathBuf,
         source: std::io::Error,
     },
+    #[error("lock file {path:?}")]
+    LockFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("can not generate the file {dst:?} from the folder {src:?}")]
+    FileFromFolder { src: PathBuf, dst: PathBuf },
     #[error("remove file {path:?}")]
     RemoveFile {
         path: PathBuf,
@@ -112,6 +137,41 @@ pub enum Error {
         subfolder: Option<PathBuf>,
     },
 
+    #[error("cycle of imports: {chain}")]
+    ImportCycle { chain: String },
+
+    #[error("imports of {uri:?} conflict (different revisions or overlapping subfolders), use `--import-conflict` to choose one:\n  {first}\n  {second}")]
+    ImportConflict {
+        uri: String,
+        first: String,
+        second: String,
+    },
+
+    #[error("destination {destination:?} of the import of {uri:?} should be a relative path (inside the destination folder)")]
+    ImportDestination { uri: String, destination: String },
+
+    #[error("invalid directory {path:?}: {msg}")]
+    InvalidDirectory { path: String, msg: String },
+
+    #[error("invalid path of removed or renamed file {path:?}: {msg}")]
+    InvalidUpgradePath { path: String, msg: String },
+
+    #[error("invalid rule for the files {path:?}: {msg}")]
+    InvalidFileRule { path: String, msg: String },
+
+    #[error("fail to format {path:?} with '{cmd}': {msg}")]
+    Format {
+        path: PathBuf,
+        cmd: String,
+        msg: String,
+    },
+
+    #[error("invalid patch of {path:?}: {msg}")]
+    InvalidPatch { path: String, msg: String },
+
+    #[error("fail to deep merge {path:?} (supported formats: json, yaml, toml): {msg}")]
+    DeepMerge { path: PathBuf, msg: String },
+
     #[error("Application directory not found")]
     ApplicationPathNotFound {},
 
@@ -134,6 +194,12 @@ pub enum Error {
         template: String,
         source: handlebars::RenderError,
     },
+    #[error("fail to process template '{template}' when {when}")]
+    Jinja {
+        when: String,
+        template: String,
+        source: minijinja::Error,
+    },
     // #[error(transparent)]
     #[error("fail to process yaml")]
     SerdeYaml {
@@ -147,6 +213,12 @@ pub enum Error {
         script: String,
         source: run_script::ScriptError,
     },
+    #[error("script '{script}' failed with exit code {code}")]
+    ScriptFailed { script: String, code: i32 },
+    #[error("script '{script}' killed after {timeout:?}")]
+    ScriptTimeout { script: String, timeout: Duration },
+    #[error("invalid script: {msg}")]
+    InvalidScript { msg: String },
     #[error(transparent)]
     SerdeJson {
         #[from]
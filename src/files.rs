@@ -1,10 +1,12 @@
 use crate::path_pattern::PathPattern;
-use crate::Result;
+use crate::{Error, Result};
+use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use walkdir::WalkDir;
 
 pub const FILEEXT_HANDLEBARS: &str = ".ffizer.hbs";
+pub const FILEEXT_JINJA: &str = ".ffizer.j2";
 pub const FILEEXT_RAW: &str = ".ffizer.raw";
 
 #[derive(Debug, Clone, PartialEq, Eq, Default, Hash, Ord, PartialOrd)]
@@ -39,6 +41,20 @@ pub fn is_ffizer_handlebars(path: &Path) -> bool {
         .unwrap_or(false)
 }
 
+pub fn is_ffizer_jinja(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .map(|str| str.contains(FILEEXT_JINJA))
+        .unwrap_or(false)
+}
+
+pub fn is_ffizer_raw(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .map(|str| str.contains(FILEEXT_RAW))
+        .unwrap_or(false)
+}
+
 pub fn remove_special_suffix(path: &Path) -> Result<PathBuf> {
     match path.file_name().and_then(|s| s.to_str()) {
         None => Ok(path.to_path_buf()),
@@ -54,6 +70,8 @@ fn remove_special_suffix_on_filename(v: &str) -> String {
         v.replacen(FILEEXT_RAW, "", 1)
     } else if v.contains(FILEEXT_HANDLEBARS) {
         v.replacen(FILEEXT_HANDLEBARS, "", 1)
+    } else if v.contains(FILEEXT_JINJA) {
+        v.replacen(FILEEXT_JINJA, "", 1)
     } else {
         v.to_owned()
     }
@@ -70,13 +88,73 @@ where
     )))
 }
 
-pub fn find_childpaths<P>(base: P, ignores: &[PathPattern]) -> Vec<ChildPath>
+/// create a symlink at `path` to `target` (relative to the folder of `path` or absolute)
+pub fn create_symlink(target: &Path, path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    let r = std::os::unix::fs::symlink(target, path);
+    #[cfg(windows)]
+    let r = if path
+        .parent()
+        .map(|p| p.join(target).is_dir())
+        .unwrap_or(false)
+    {
+        std::os::windows::fs::symlink_dir(target, path)
+    } else {
+        std::os::windows::fs::symlink_file(target, path)
+    };
+    r.map_err(|source| Error::CreateSymlink {
+        path: path.to_path_buf(),
+        target: target.to_path_buf(),
+        source,
+    })
+}
+
+/// copy the content of the folder `src` into `dst` (created), the symlinks are copied as symlinks
+/// and the entries named as one of `excludes` (at any level) are skipped
+pub fn copy_folder(src: &Path, dst: &Path, excludes: &[&str]) -> Result<()> {
+    let entries = WalkDir::new(src).into_iter().filter_entry(|e| {
+        e.depth() == 0
+            || !e
+                .file_name()
+                .to_str()
+                .map(|name| excludes.contains(&name))
+                .unwrap_or(false)
+    });
+    for entry in entries {
+        let entry = entry?;
+        let path = dst.join(
+            entry
+                .path()
+                .strip_prefix(src)
+                .expect("scanned child path to be under base"),
+        );
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&path).map_err(|source| Error::CreateFolder { path, source })?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(entry.path()).map_err(|source| Error::ReadFile {
+                path: entry.path().to_path_buf(),
+                source,
+            })?;
+            create_symlink(&target, &path)?;
+        } else {
+            fs::copy(entry.path(), &path).map_err(|source| Error::CopyFile {
+                src: entry.path().to_path_buf(),
+                dst: path,
+                source,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+pub fn find_childpaths<P>(base: P, ignores: &[PathPattern], follow_links: bool) -> Vec<ChildPath>
 where
     P: AsRef<Path>,
 {
     let base = base.as_ref();
     WalkDir::new(base)
-        .follow_links(false)
+        .follow_links(follow_links)
         .into_iter()
         .filter_entry(|e| {
             e.path()
@@ -127,6 +205,9 @@ mod tests {
             ("foo.ffizer.raw.ffizer.raw.json", "foo.ffizer.raw.json"),
             ("foo.ffizer.raw.ffizer.hbs.json", "foo.ffizer.hbs.json"),
             ("foo.json.ffizer.raw.ffizer.hbs", "foo.json.ffizer.hbs"),
+            ("foo.json.ffizer.j2", "foo.json"),
+            ("foo.ffizer.j2.json", "foo.json"),
+            ("foo.json.ffizer.raw.ffizer.j2", "foo.json.ffizer.j2"),
         ] {
             assert_that!(remove_special_suffix_on_filename(input))
                 .is_equal_to(expected.to_string());
@@ -141,4 +222,23 @@ mod tests {
             .is_equal_to(&PathBuf::from("foo.ext1.REMOTE"));
         Ok(())
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_folder() {
+        let tmp_dir = tempfile::TempDir::new().expect("create a temp dir");
+        let src = tmp_dir.path().join("src");
+        fs::create_dir_all(src.join(".git")).unwrap();
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join(".git").join("HEAD"), "ref").unwrap();
+        fs::write(src.join("sub").join("foo.txt"), "foo").unwrap();
+        create_symlink(Path::new("sub/foo.txt"), &src.join("link.txt")).unwrap();
+        let dst = tmp_dir.path().join("dst");
+        copy_folder(&src, &dst, &[".git"]).unwrap();
+        assert_that!(fs::read_to_string(dst.join("sub").join("foo.txt")).unwrap())
+            .is_equal_to("foo".to_owned());
+        assert_that!(fs::read_link(dst.join("link.txt")).unwrap())
+            .is_equal_to(PathBuf::from("sub/foo.txt"));
+        assert_that!(dst.join(".git").exists()).is_false();
+    }
 }
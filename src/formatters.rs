@@ -0,0 +1,91 @@
+//! format the content of the generated files with external commands (see `formatters` of `.ffizer.yaml`),
+//! before the comparison with the existing files.
+use crate::error::*;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// the content formatted by `cmd` (run by the shell, the content on stdin, the result on stdout),
+/// `path` is the path of the generated file (provided as `$FFIZER_PATH`)
+pub(crate) fn format(cmd: &str, path: &Path, content: &[u8]) -> Result<Vec<u8>> {
+    let to_error = |msg: String| Error::Format {
+        path: path.to_path_buf(),
+        cmd: cmd.to_owned(),
+        msg,
+    };
+    let mut command = if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(cmd);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(cmd);
+        c
+    };
+    if let Some(folder) = path.parent().filter(|p| p.is_dir()) {
+        command.current_dir(folder);
+    }
+    let mut child = command
+        .env("FFIZER_PATH", path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|source| Error::RunCommand {
+            cmd: cmd.to_owned(),
+            source,
+        })?;
+    // write from an other thread, to not block if the command writes before the end of its input
+    let mut stdin = child.stdin.take().expect("stdin of the formatter is piped");
+    let input = content.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child
+        .wait_with_output()
+        .map_err(|source| Error::RunCommand {
+            cmd: cmd.to_owned(),
+            source,
+        })?;
+    if !output.status.success() {
+        return Err(to_error(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // a command that succeeds without reading (all) its input closes the pipe
+    match writer
+        .join()
+        .map_err(|_| to_error("fail to write the content".to_owned()))?
+    {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(to_error(e.to_string())),
+        _ => Ok(output.stdout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_format_with_command() {
+        let actual = format("tr a-z A-Z", Path::new("/tmp/foo.txt"), b"hello\n").unwrap();
+        assert_eq!(actual, b"HELLO\n".to_vec());
+        let actual = format("echo $FFIZER_PATH", Path::new("/tmp/foo.txt"), b"").unwrap();
+        assert_eq!(actual, b"/tmp/foo.txt\n".to_vec());
+        assert!(format("exit 1", Path::new("/tmp/foo.txt"), b"hello").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_format_with_command_ignoring_stdin() {
+        // larger than the buffer of a pipe, so the write fails when the command exits
+        let content = vec![b'a'; 1024 * 1024];
+        let actual = format("echo x", Path::new("/tmp/foo.txt"), &content).unwrap();
+        assert_eq!(actual, b"x\n".to_vec());
+        let actual = format("true", Path::new("/tmp/foo.txt"), &content).unwrap();
+        assert_eq!(actual, b"".to_vec());
+        assert!(format("exit 1", Path::new("/tmp/foo.txt"), &content).is_err());
+    }
+}
@@ -1,43 +1,47 @@
+use crate::cli_opt::GitOpts;
 use crate::error::*;
+use crate::source_uri::SourceUri;
+use crate::user_cfg::UserCfg;
 use git2::build::{CheckoutBuilder, RepoBuilder};
-use git2::{Config, FetchOptions, Repository};
-use std::path::Path;
+use git2::{Config, Cred, CredentialType, FetchOptions, Repository, SubmoduleUpdateOptions};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use tracing::{debug, info, warn};
 
+/// environment variable used to provide a token for https remotes
+pub const ENV_GIT_TOKEN: &str = "FFIZER_GIT_TOKEN";
+/// username used with the token when none is defined (accepted by github, gitlab,...)
+const DEFAULT_TOKEN_USERNAME: &str = "oauth2";
+
 /// clone a repository at a rev to a directory
+/// if `submodules` is true, then submodules are initialized and updated recursively
 // TODO id the directory is already present then fetch and rebase (if not in offline mode)
-#[tracing::instrument(fields(dst = ?dst.as_ref(), url = url.as_ref(), rev = rev.as_ref()))]
-pub fn retrieve<P, U, R>(dst: P, url: U, rev: R) -> Result<(), Error>
+#[tracing::instrument(skip(git_opts), fields(dst = ?dst.as_ref(), url = url.as_ref(), rev = rev.as_ref()))]
+pub fn retrieve<P, U, R>(
+    dst: P,
+    url: U,
+    rev: R,
+    submodules: bool,
+    git_opts: &GitOpts,
+) -> Result<(), Error>
 where
     P: AsRef<Path>,
     R: AsRef<str>,
     U: AsRef<str>,
 {
     let dst = dst.as_ref();
-    let mut fo = make_fetch_options().map_err(|source| Error::GitRetrieve {
-        msg: "make_fetch_options".to_owned(),
-        dst: dst.to_path_buf(),
-        url: url.as_ref().to_owned(),
-        rev: rev.as_ref().to_owned(),
-        source,
-    })?;
+    let user_cfg = &git_opts.user_cfg;
+    let auth = AuthSettings::find(url.as_ref(), git_opts, user_cfg);
+    let to_error = |msg: &str, source: git2::Error| {
+        retrieve_error(msg, dst, url.as_ref(), rev.as_ref(), source)
+    };
+    let mut fo =
+        make_fetch_options(&auth).map_err(|source| to_error("make_fetch_options", source))?;
     if dst.exists() {
         info!("git reset cached template");
-        checkout(dst, &rev).map_err(|source| Error::GitRetrieve {
-            msg: "checkout_reset".to_owned(),
-            dst: dst.to_path_buf(),
-            url: url.as_ref().to_owned(),
-            rev: rev.as_ref().to_owned(),
-            source,
-        })?;
+        checkout(dst, &rev).map_err(|source| to_error("checkout_reset", source))?;
         info!("git pull cached template");
-        pull(dst, &rev, &mut fo).map_err(|source| Error::GitRetrieve {
-            msg: "pull".to_owned(),
-            dst: dst.to_path_buf(),
-            url: url.as_ref().to_owned(),
-            rev: rev.as_ref().to_owned(),
-            source,
-        })?;
+        pull(dst, &rev, &mut fo).map_err(|source| to_error("pull", source))?;
     //until pull is fixed and work as expected
     // let mut tmp = dst.to_path_buf().clone();
     // tmp.set_extension("part");
@@ -50,26 +54,146 @@ where
     // std::fs::rename(&tmp, &dst)?;
     } else {
         info!("git clone into cached template");
-        clone(dst, &url, "master", fo)?;
-        checkout(dst, &rev).map_err(|source| Error::GitRetrieve {
-            msg: "checkout_clone".to_owned(),
+        // clone into a temporary folder, so an interrupted or failed clone is never seen as a cached template
+        let tmp = crate::files::add_suffix(dst, ".part")?;
+        if tmp.exists() {
+            std::fs::remove_dir_all(&tmp).map_err(|source| Error::RemoveFolder {
+                path: tmp.clone(),
+                source,
+            })?;
+        }
+        let cloned = clone(&tmp, &url, "master", fo).and_then(|_| {
+            checkout(&tmp, &rev).map_err(|source| to_error("checkout_clone", source))
+        });
+        if let Err(err) = cloned {
+            if let Err(rm_err) = std::fs::remove_dir_all(&tmp) {
+                warn!(path = ?tmp, error = ?rm_err, "failed to remove partial clone");
+            }
+            return Err(err);
+        }
+        std::fs::rename(&tmp, dst).map_err(|source| Error::RenameFile {
+            src: tmp,
             dst: dst.to_path_buf(),
-            url: url.as_ref().to_owned(),
-            rev: rev.as_ref().to_owned(),
             source,
         })?;
     }
+    if submodules {
+        info!("git update submodules of cached template");
+        update_submodules(dst, git_opts, user_cfg)
+            .map_err(|source| to_error("update_submodules", source))?;
+    }
     Ok(())
 }
 
-/// a best attempt effort is made to authenticate
+/// authentication failures are reported with hints about the way to provide credentials
+fn retrieve_error(msg: &str, dst: &Path, url: &str, rev: &str, source: git2::Error) -> Error {
+    if source.code() == git2::ErrorCode::Auth {
+        Error::GitAuthentication {
+            url: url.to_owned(),
+            user_cfg: UserCfg::find_user_cfg_file().unwrap_or_default(),
+            source,
+        }
+    } else {
+        Error::GitRetrieve {
+            msg: msg.to_owned(),
+            dst: dst.to_path_buf(),
+            url: url.to_owned(),
+            rev: rev.to_owned(),
+            source,
+        }
+    }
+}
+
+/// init and update (recursively) the submodules of the repository at `dst`
+fn update_submodules<P>(dst: P, git_opts: &GitOpts, user_cfg: &UserCfg) -> Result<(), git2::Error>
+where
+    P: AsRef<Path>,
+{
+    let repository = Repository::open(dst.as_ref())?;
+    for mut submodule in repository.submodules()? {
+        debug!(name = ?submodule.name(), path = ?submodule.path(), "update submodule");
+        let auth = AuthSettings::find(submodule.url().unwrap_or_default(), git_opts, user_cfg);
+        let mut so = SubmoduleUpdateOptions::new();
+        so.fetch(make_fetch_options(&auth)?);
+        submodule.update(true, Some(&mut so))?;
+        update_submodules(dst.as_ref().join(submodule.path()), git_opts, user_cfg)?;
+    }
+    Ok(())
+}
+
+/// credentials explicitly provided for a remote,
+/// by priority: cli / environment, then the settings of the host from the user configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct AuthSettings {
+    ssh_key: Option<PathBuf>,
+    username: Option<String>,
+    token: Option<String>,
+}
+
+impl AuthSettings {
+    fn find(url: &str, git_opts: &GitOpts, user_cfg: &UserCfg) -> AuthSettings {
+        Self::find_with_env(url, git_opts, user_cfg, |name| std::env::var(name).ok())
+    }
+
+    /// like `find`, with the environment variables read by `env`
+    fn find_with_env<F>(url: &str, git_opts: &GitOpts, user_cfg: &UserCfg, env: F) -> AuthSettings
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let host_cfg = SourceUri::from_str(url)
+            .ok()
+            .and_then(|uri| uri.host)
+            .and_then(|host| user_cfg.find_host(&host).cloned())
+            .unwrap_or_default();
+        let token = if url.starts_with("https://") || url.starts_with("http://") {
+            env(ENV_GIT_TOKEN)
+                .filter(|v| !v.is_empty())
+                .or_else(|| host_cfg.token(&env))
+        } else {
+            None
+        };
+        AuthSettings {
+            ssh_key: git_opts.ssh_key.clone().or_else(|| host_cfg.ssh_key_path()),
+            username: host_cfg.username.clone(),
+            token,
+        }
+    }
+}
+
+/// explicit credentials (ssh key, token) are tried first,
+/// then a best attempt effort is made to authenticate
 /// requests when required to support private
 /// git repositories
-fn make_fetch_options<'a>() -> Result<FetchOptions<'a>, git2::Error> {
+fn make_fetch_options<'a>(auth: &AuthSettings) -> Result<FetchOptions<'a>, git2::Error> {
     let mut cb = git2::RemoteCallbacks::new();
     let git_config = git2::Config::open_default()?;
     let mut ch = git2_credentials::CredentialHandler::new(git_config);
-    cb.credentials(move |url, username, allowed| ch.try_next_credential(url, username, allowed));
+    let auth = auth.clone();
+    let mut ssh_key_tried = false;
+    let mut token_tried = false;
+    cb.credentials(move |url, username, allowed| {
+        if let Some(ssh_key) = auth.ssh_key.as_ref().filter(|_| !ssh_key_tried) {
+            if allowed.contains(CredentialType::SSH_KEY) {
+                ssh_key_tried = true;
+                debug!(?ssh_key, "try ssh key");
+                return Cred::ssh_key(username.unwrap_or("git"), None, ssh_key, None);
+            }
+        }
+        if let Some(token) = auth.token.as_ref().filter(|_| !token_tried) {
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                token_tried = true;
+                debug!("try token");
+                let username = auth
+                    .username
+                    .as_deref()
+                    .or(username)
+                    .unwrap_or(DEFAULT_TOKEN_USERNAME);
+                return Cred::userpass_plaintext(username, token);
+            }
+        }
+        ch.try_next_credential(url, username, allowed)
+            .map_err(|e| git2::Error::new(git2::ErrorCode::Auth, e.class(), e.message()))
+    });
 
     let mut fo = FetchOptions::new();
     let mut proxy_options = git2::ProxyOptions::new();
@@ -95,12 +219,8 @@ where
         .branch(rev.as_ref())
         .fetch_options(fo)
         .clone(url.as_ref(), dst.as_ref())
-        .map_err(|source| Error::GitRetrieve {
-            msg: "clone".to_owned(),
-            dst: dst.as_ref().to_path_buf(),
-            url: url.as_ref().to_owned(),
-            rev: rev.as_ref().to_owned(),
-            source,
+        .map_err(|source| {
+            retrieve_error("clone", dst.as_ref(), url.as_ref(), rev.as_ref(), source)
         })?;
     Ok(())
 }
@@ -270,6 +390,64 @@ mod tests {
     use tempfile::tempdir;
     use tracing_subscriber::FmtSubscriber;
 
+    #[test]
+    fn auth_settings_should_prefer_cli_over_host_cfg() {
+        let env = |name: &str| (name == "FFIZER_TEST_AUTH_TOKEN").then(|| "secret".to_owned());
+        let user_cfg = UserCfg::from_str(
+            r#"
+            hosts:
+                github.com:
+                    ssh_key: /keys/id_github
+                    username: bot
+                    token_env: FFIZER_TEST_AUTH_TOKEN
+            "#,
+        )
+        .unwrap();
+        let auth = AuthSettings::find_with_env(
+            "git@github.com:ffizer/ffizer.git",
+            &GitOpts::default(),
+            &user_cfg,
+            env,
+        );
+        assert_eq!(auth.ssh_key, Some(PathBuf::from("/keys/id_github")));
+        assert_eq!(auth.token, None, "token only for https");
+
+        let git_opts = GitOpts {
+            ssh_key: Some(PathBuf::from("/keys/id_cli")),
+            ..Default::default()
+        };
+        let auth = AuthSettings::find_with_env(
+            "https://github.com/ffizer/ffizer.git",
+            &git_opts,
+            &user_cfg,
+            env,
+        );
+        assert_eq!(auth.ssh_key, Some(PathBuf::from("/keys/id_cli")));
+        assert_eq!(auth.username.as_deref(), Some("bot"));
+        assert_eq!(auth.token.as_deref(), Some("secret"));
+
+        let auth = AuthSettings::find_with_env(
+            "https://gitlab.com/foo/bar.git",
+            &git_opts,
+            &user_cfg,
+            env,
+        );
+        assert_eq!(auth.username, None);
+        assert_eq!(auth.token, None);
+
+        let auth = AuthSettings::find_with_env(
+            "https://github.com/ffizer/ffizer.git",
+            &git_opts,
+            &user_cfg,
+            |name| Some(format!("value of {}", name)),
+        );
+        assert_eq!(
+            auth.token,
+            Some(format!("value of {}", ENV_GIT_TOKEN)),
+            "the token of the environment before the one of the host"
+        );
+    }
+
     #[cfg(not(target_os = "windows"))]
     #[test]
     fn retrieve_should_update_existing_template() {
@@ -324,7 +502,14 @@ mod tests {
                 warn!(%output, %error);
             }
             assert_eq!(code, 0, "setup template v1");
-            retrieve(&dst_path, src_path.to_str().unwrap(), "master").unwrap();
+            retrieve(
+                &dst_path,
+                src_path.to_str().unwrap(),
+                "master",
+                false,
+                &GitOpts::default(),
+            )
+            .unwrap();
             assert_eq!(
                 fs::read_to_string(dst_path.join("foo.txt")).unwrap(),
                 "v1: Lorem ipsum\n"
@@ -355,7 +540,14 @@ mod tests {
             }
             assert_eq!(code, 0, "setup template v2");
 
-            retrieve(&dst_path, src_path.to_str().unwrap(), "master").unwrap();
+            retrieve(
+                &dst_path,
+                src_path.to_str().unwrap(),
+                "master",
+                false,
+                &GitOpts::default(),
+            )
+            .unwrap();
             assert_eq!(
                 fs::read_to_string(dst_path.join("foo.txt")).unwrap(),
                 "v2: Hello\n"
@@ -386,7 +578,14 @@ mod tests {
             }
             assert_eq!(code, 0, "setup template v3");
 
-            retrieve(&dst_path, src_path.to_str().unwrap(), "master").unwrap();
+            retrieve(
+                &dst_path,
+                src_path.to_str().unwrap(),
+                "master",
+                false,
+                &GitOpts::default(),
+            )
+            .unwrap();
             assert_eq!(
                 fs::read_to_string(dst_path.join("foo.txt")).unwrap(),
                 "v3: Hourra\n"
@@ -394,4 +593,157 @@ mod tests {
         }
         fs::remove_dir_all(tmp_dir).expect("remove tmp dir");
     }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn retrieve_should_init_submodules() {
+        if std::process::Command::new("git")
+            .arg("version")
+            .output()
+            .is_err()
+        {
+            eprintln!("skip the test because `git` is not installed");
+            return;
+        }
+
+        let tmp_dir = tempdir().unwrap();
+
+        let shared_path = tmp_dir.path().join("shared");
+        let src_path = tmp_dir.path().join("src");
+        let dst_path = tmp_dir.path().join("dst");
+        let options = run_script::ScriptOptions::new();
+        let args = vec![];
+
+        let (code, output, error) = run_script::run(
+            &format!(
+                r#"
+                    mkdir -p {shared}
+                    cd {shared}
+                    git init -b master
+                    git config user.email "test@example.com"
+                    git config user.name "Test Name"
+                    echo "shared: Lorem ipsum" > bar.txt
+                    git add bar.txt
+                    git commit -m "add bar.txt"
+                    mkdir -p {src}
+                    cd {src}
+                    git init -b master
+                    git config user.email "test@example.com"
+                    git config user.name "Test Name"
+                    echo "v1: Lorem ipsum" > foo.txt
+                    git add foo.txt
+                    git -c protocol.file.allow=always submodule add {shared} shared
+                    git commit -m "add foo.txt and submodule"
+                    "#,
+                shared = shared_path.to_str().unwrap(),
+                src = src_path.to_str().unwrap(),
+            ),
+            &args,
+            &options,
+        )
+        .unwrap();
+        if code != 0 {
+            warn!(%output, %error);
+        }
+        assert_eq!(code, 0, "setup template with submodule");
+
+        retrieve(
+            &dst_path,
+            src_path.to_str().unwrap(),
+            "master",
+            true,
+            &GitOpts::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dst_path.join("foo.txt")).unwrap(),
+            "v1: Lorem ipsum\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dst_path.join("shared").join("bar.txt")).unwrap(),
+            "shared: Lorem ipsum\n"
+        );
+        fs::remove_dir_all(tmp_dir).expect("remove tmp dir");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn retrieve_should_keep_cache_when_remote_is_unreachable() {
+        if std::process::Command::new("git")
+            .arg("version")
+            .output()
+            .is_err()
+        {
+            eprintln!("skip the test because `git` is not installed");
+            return;
+        }
+
+        let tmp_dir = tempdir().unwrap();
+
+        let src_path = tmp_dir.path().join("src");
+        let dst_path = tmp_dir.path().join("dst");
+        let options = run_script::ScriptOptions::new();
+        let args = vec![];
+
+        let (code, output, error) = run_script::run(
+            &format!(
+                r#"
+                    mkdir -p {src}
+                    cd {src}
+                    git init -b master
+                    git config user.email "test@example.com"
+                    git config user.name "Test Name"
+                    echo "v1: Lorem ipsum" > foo.txt
+                    git add foo.txt
+                    git commit -m "add foo.txt"
+                    "#,
+                src = src_path.to_str().unwrap(),
+            ),
+            &args,
+            &options,
+        )
+        .unwrap();
+        if code != 0 {
+            warn!(%output, %error);
+        }
+        assert_eq!(code, 0, "setup template");
+
+        let missing_path = tmp_dir.path().join("missing");
+        let missing_dst_path = tmp_dir.path().join("missing_dst");
+        assert!(retrieve(
+            &missing_dst_path,
+            missing_path.to_str().unwrap(),
+            "master",
+            false,
+            &GitOpts::default()
+        )
+        .is_err());
+        assert!(!missing_dst_path.exists(), "failed clone is not cached");
+        assert!(!crate::files::add_suffix(&missing_dst_path, ".part")
+            .unwrap()
+            .exists());
+
+        retrieve(
+            &dst_path,
+            src_path.to_str().unwrap(),
+            "master",
+            false,
+            &GitOpts::default(),
+        )
+        .unwrap();
+        fs::remove_dir_all(&src_path).unwrap();
+        assert!(retrieve(
+            &dst_path,
+            src_path.to_str().unwrap(),
+            "master",
+            false,
+            &GitOpts::default()
+        )
+        .is_err());
+        assert_eq!(
+            fs::read_to_string(dst_path.join("foo.txt")).unwrap(),
+            "v1: Lorem ipsum\n"
+        );
+        fs::remove_dir_all(tmp_dir).expect("remove tmp dir");
+    }
 }
@@ -3,16 +3,21 @@ pub trait Graph {
     type V;
 
     fn find_node(&self, k: &Self::K) -> Option<&Self::V>;
-    fn find_edges_direct(&self, v: &Self::V) -> Vec<Self::K>;
-    fn find_edges_ordered_by_depth(&self, root_key: &Self::K) -> Vec<Self::K> {
-        let mut back = vec![root_key.clone()];
+    fn find_edges_direct(&self, k: &Self::K, v: &Self::V) -> Vec<Self::K>;
+    /// the keys reachable from the root (included), ordered by depth (breadth-first),
+    /// with the path (from the root) used to reach each key (the last item of the path)
+    fn find_paths_ordered_by_depth(&self, root_key: &Self::K) -> Vec<Vec<Self::K>> {
+        let mut back = vec![vec![root_key.clone()]];
         let mut visited = 0;
         while visited < back.len() {
-            let k = back.get(visited).expect("should be present");
+            let path = back.get(visited).expect("should be present").clone();
+            let k = path.last().expect("path should not be empty");
             if let Some(v) = self.find_node(k) {
-                for child in self.find_edges_direct(v) {
-                    if !back.contains(&child) {
-                        back.push(child.clone())
+                for child in self.find_edges_direct(k, v) {
+                    if !back.iter().any(|p| p.last() == Some(&child)) {
+                        let mut child_path = path.clone();
+                        child_path.push(child);
+                        back.push(child_path);
                     }
                 }
             }
@@ -20,6 +25,40 @@ pub trait Graph {
         }
         back
     }
+
+    /// find a path from the root that ends on a key already in the path (a cycle)
+    fn find_cycle(&self, root_key: &Self::K) -> Option<Vec<Self::K>> {
+        let mut path = vec![root_key.clone()];
+        let mut dead_ends = vec![];
+        find_cycle_from(self, &mut path, &mut dead_ends)
+    }
+}
+
+/// depth-first search of a cycle, `dead_ends` are the keys already explored without cycle
+fn find_cycle_from<G: Graph + ?Sized>(
+    graph: &G,
+    path: &mut Vec<G::K>,
+    dead_ends: &mut Vec<G::K>,
+) -> Option<Vec<G::K>> {
+    let k = path.last().expect("path should not be empty").clone();
+    if let Some(v) = graph.find_node(&k) {
+        for child in graph.find_edges_direct(&k, v) {
+            if path.contains(&child) {
+                let mut cycle = path.clone();
+                cycle.push(child);
+                return Some(cycle);
+            }
+            if !dead_ends.contains(&child) {
+                path.push(child);
+                if let Some(cycle) = find_cycle_from(graph, path, dead_ends) {
+                    return Some(cycle);
+                }
+                path.pop();
+            }
+        }
+    }
+    dead_ends.push(k);
+    None
 }
 
 #[cfg(test)]
@@ -38,12 +77,12 @@ mod tests {
         fn find_node(&self, k: &Self::K) -> Option<&Self::V> {
             self.datas.get(k)
         }
-        fn find_edges_direct(&self, v: &Self::V) -> Vec<Self::K> {
+        fn find_edges_direct(&self, _k: &Self::K, v: &Self::V) -> Vec<Self::K> {
             v.clone()
         }
     }
     #[test]
-    fn test_find_edges_ordered_by_depth() {
+    fn test_find_paths_ordered_by_depth_visit_each_key_once() {
         let mut datas = BTreeMap::new();
         datas.insert("k1".to_owned(), vec!["k1.1".to_owned(), "k1.2".to_owned()]);
         datas.insert(
@@ -75,6 +114,44 @@ mod tests {
             "k1.2.2".to_owned(),
             "k1.1.2.1".to_owned(),
         ];
-        assert_that!(&(g.find_edges_ordered_by_depth(&"k1".to_owned()))).is_equal_to(&expected);
+        let actual = g
+            .find_paths_ordered_by_depth(&"k1".to_owned())
+            .into_iter()
+            .filter_map(|mut path| path.pop())
+            .collect::<Vec<_>>();
+        assert_that!(&actual).is_equal_to(&expected);
+    }
+
+    #[test]
+    fn test_find_paths_ordered_by_depth() {
+        let mut datas = BTreeMap::new();
+        datas.insert("k1".to_owned(), vec!["k1.1".to_owned(), "k1.2".to_owned()]);
+        datas.insert("k1.1".to_owned(), vec!["k1.1.1".to_owned()]);
+        datas.insert("k1.2".to_owned(), vec!["k1.1.1".to_owned()]);
+        let g = MyGraph { datas };
+        let expected = vec![
+            vec!["k1".to_owned()],
+            vec!["k1".to_owned(), "k1.1".to_owned()],
+            vec!["k1".to_owned(), "k1.2".to_owned()],
+            vec!["k1".to_owned(), "k1.1".to_owned(), "k1.1.1".to_owned()],
+        ];
+        assert_that!(&(g.find_paths_ordered_by_depth(&"k1".to_owned()))).is_equal_to(&expected);
+        assert_that!(&(g.find_cycle(&"k1".to_owned()))).is_none();
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        let mut datas = BTreeMap::new();
+        datas.insert("k1".to_owned(), vec!["k1.1".to_owned(), "k1.2".to_owned()]);
+        datas.insert("k1.2".to_owned(), vec!["k1.2.1".to_owned()]);
+        datas.insert("k1.2.1".to_owned(), vec!["k1".to_owned()]);
+        let g = MyGraph { datas };
+        let expected = vec![
+            "k1".to_owned(),
+            "k1.2".to_owned(),
+            "k1.2.1".to_owned(),
+            "k1".to_owned(),
+        ];
+        assert_that!(&(g.find_cycle(&"k1".to_owned()))).is_equal_to(Some(expected));
     }
 }
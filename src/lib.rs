@@ -8,15 +8,25 @@ pub mod tools;
 
 mod cfg;
 mod cli_opt;
+mod deep_merge;
+mod engine;
+mod eol;
 mod files;
+mod formatters;
 mod git;
 mod graph;
+mod managed_blocks;
+mod patches;
 mod path_pattern;
+mod record;
 mod scripts;
 mod source_file;
 mod source_loc;
 mod source_uri;
+mod template_helpers;
+mod text;
 mod ui;
+mod user_cfg;
 mod variable_def;
 mod variables;
 
@@ -24,13 +34,18 @@ pub use crate::cfg::provide_json_schema;
 pub use crate::cli_opt::*;
 pub use crate::source_loc::SourceLoc;
 pub use crate::source_uri::SourceUri;
+pub use crate::user_cfg::UserCfg;
 
-use crate::cfg::{render_composite, TemplateComposite};
+use crate::cfg::{render_composite, Eol, FileStrategy, OnFailure, TemplateComposite};
+use crate::engine::{Engine, Engines};
 use crate::error::*;
 use crate::files::ChildPath;
+use crate::patches::Patch;
+use crate::path_pattern::PathPattern;
+use crate::record::Record;
 use crate::source_file::{SourceFile, SourceFileMetadata};
 use crate::variables::Variables;
-use handlebars_misc_helpers::new_hbs;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use tracing::{debug, warn};
@@ -45,8 +60,28 @@ pub enum FileOperation {
     Nothing,
     Ignore,
     MkDir,
+    MkSymlink,
     AddFile,
     UpdateFile,
+    /// move the existing file `from` (relative to the destination folder)
+    MoveFile {
+        from: PathBuf,
+    },
+    DeleteFile,
+    /// insert the snippets of the patches (executed after the other operations)
+    PatchFile {
+        patches: Vec<Patch>,
+    },
+}
+
+impl FileOperation {
+    /// operation on the files of a previous apply (executed before the other operations)
+    fn is_upgrade(&self) -> bool {
+        matches!(
+            self,
+            FileOperation::MoveFile { .. } | FileOperation::DeleteFile
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -57,30 +92,83 @@ pub struct Action {
     pub operation: FileOperation,
 }
 
+impl Action {
+    /// the command to format the file generated by the action (see `formatters`)
+    pub fn formatter(&self) -> Option<&str> {
+        match self.operation {
+            FileOperation::AddFile | FileOperation::UpdateFile => {
+                self.src.first().and_then(|s| s.formatter.as_deref())
+            }
+            _ => None,
+        }
+    }
+}
+
 pub fn process(ctx: &Ctx) -> Result<()> {
     debug!("extracting variables from cli",);
     let variables_from_cli = extract_variables(ctx)?;
+    let git_opts = GitOpts {
+        user_cfg: UserCfg::load_or_default(),
+        ..ctx.cmd_opt.git.clone()
+    };
     debug!("compositing templates");
-    let mut template_composite =
-        TemplateComposite::from_src(&variables_from_cli, ctx.cmd_opt.offline, &ctx.cmd_opt.src)?;
-    debug!(variables_from_cli = ?variables_from_cli, "asking variables");
-    let variables = ui::ask_variables(
-        ctx,
-        &template_composite.find_variablecfgs()?,
-        variables_from_cli,
+    let mut template_composite = TemplateComposite::from_src(
+        &variables_from_cli,
+        ctx.cmd_opt.offline,
+        &git_opts,
+        &ctx.cmd_opt.import_conflict,
+        &ctx.cmd_opt.src,
     )?;
+    debug!(variables_from_cli = ?variables_from_cli, "asking variables");
+    ui::write_title("Configure variables")?;
+    let mut variables = variables_from_cli;
+    loop {
+        variables = ui::ask_variables(ctx, &template_composite.find_variablecfgs()?, variables)?;
+        // the variables of the (conditional) imported layers are asked on the next loop
+        if !template_composite.include_conditional_imports(&variables)? {
+            break;
+        }
+    }
     // update cfg(s) with variables defined by user (use to update ignore, scripts,...)
     debug!(variables = ?variables, "update template_composite with variables");
-    template_composite = render_composite(&template_composite, &variables, true)?;
+    let layers_variables = template_composite.find_layers_variables(&variables)?;
+    let layers_destinations = template_composite.find_layers_destinations(&layers_variables)?;
+    template_composite = render_composite(&template_composite, &layers_variables, true)?;
     debug!("listing files from templates");
-    let source_files = template_composite.find_sourcefiles()?;
+    let source_files = template_composite.find_sourcefiles(ctx.cmd_opt.symlinks)?;
     debug!("defining plan of rendering");
-    let actions = plan(ctx, source_files, &variables)?;
-    if ui::confirm_plan(ctx, &actions)? {
+    let actions = plan(ctx, source_files, &layers_variables, &layers_destinations)?;
+    let record = Record::load(&ctx.cmd_opt.dst_folder)?;
+    let actions = plan_upgrades(
+        ctx,
+        actions,
+        &record
+            .as_ref()
+            .map(|r| r.files(&ctx.cmd_opt.src))
+            .unwrap_or_default(),
+        &template_composite.find_removed(&layers_destinations)?,
+        &template_composite.find_renamed(&layers_destinations)?,
+    )?;
+    let actions = plan_patches(
+        ctx,
+        actions,
+        template_composite.find_patches()?,
+        &layers_destinations,
+    )?;
+    let layers_labels = template_composite.find_layers_labels();
+    if ui::confirm_plan(ctx, &actions, &layers_labels)? {
+        let actions = confirm_formatters(ctx, actions, &layers_labels)?;
         debug!("executing plan of rendering");
-        execute(ctx, &actions, &variables)?;
+        let mut engines = Engines::default();
+        template_composite.register_helpers_and_partials(&mut engines)?;
+        let generated = execute(ctx, &actions, &layers_variables, &engines)?;
+        if ctx.cmd_opt.record || record.is_some() {
+            let mut record = record.unwrap_or_default();
+            record.set_files(&ctx.cmd_opt.src, generated);
+            record.save(&ctx.cmd_opt.dst_folder)?;
+        }
         debug!("running scripts");
-        run_scripts(ctx, &template_composite)?;
+        run_scripts(ctx, &template_composite, &layers_destinations)?;
     }
     Ok(())
 }
@@ -123,22 +211,61 @@ pub fn extract_variables(ctx: &Ctx) -> Result<Variables> {
                 "false" | "n" | "no" => "false",
                 _ => v.trim(),
             };
-            variables.insert(k, Variables::value_from_str(v)?)
+            // "ns.key" to set the value of a variable of a namespace
+            let mut path = k.split('.').map(|s| s.to_owned()).collect::<Vec<_>>();
+            let key = path.pop().expect("split should provide at least one item");
+            variables.insert_in(&path, key, Variables::value_from_str(v)?)
         })
         .collect::<Result<Vec<()>>>()?;
     Ok(variables)
 }
 
-/// list actions to execute
-fn plan(ctx: &Ctx, source_files: Vec<SourceFile>, variables: &Variables) -> Result<Vec<Action>> {
+/// list actions to execute, `layers_variables` are the variables to render each layer (by order),
+/// `layers_destinations` are the folders (relative to the destination) of each layer (by order)
+fn plan(
+    ctx: &Ctx,
+    source_files: Vec<SourceFile>,
+    layers_variables: &[Variables],
+    layers_destinations: &[PathBuf],
+) -> Result<Vec<Action>> {
     // TODO create a map (dst_path, Vec<src_path>) src_path keep the order of application (from template layer)
     // TODO change Action into enum ?
     // TODO AddFile/UpdateFile can support a list of src_path
-    let list_dst_and_src = source_files
+    let engines = Engines::default();
+    let gitattributes_eols = eol::find_gitattributes_eols(&ctx.cmd_opt.dst_folder)?;
+    let list_dst_and_src = expand_loops(source_files, layers_variables)
         .into_iter()
         .map(|source_file| {
-            compute_dst_path(ctx, source_file.childpath(), variables)
-                .map(|dst_path| (dst_path, source_file))
+            let src = match &source_file.rename_to {
+                Some(relative) => ChildPath {
+                    relative: relative.clone(),
+                    ..source_file.childpath().clone()
+                },
+                None => source_file.childpath().clone(),
+            };
+            compute_dst_path(
+                ctx,
+                &src,
+                &variables_of(&source_file, layers_variables)?,
+                engines.get(source_file.engine),
+            )
+            .map(|dst_path| {
+                let destination = &layers_destinations[source_file.layer_order];
+                if destination.as_os_str().is_empty() {
+                    dst_path
+                } else if dst_path.relative.as_os_str().is_empty() {
+                    ChildPath {
+                        relative: destination.clone(),
+                        ..dst_path
+                    }
+                } else {
+                    ChildPath {
+                        relative: destination.join(&dst_path.relative),
+                        ..dst_path
+                    }
+                }
+            })
+            .map(|dst_path| (dst_path, source_file))
         })
         .collect::<Result<Vec<_>>>()?;
     // group by destination
@@ -158,7 +285,8 @@ fn plan(ctx: &Ctx, source_files: Vec<SourceFile>, variables: &Variables) -> Resu
         .into_iter()
         .map(|(dst_path, mut src)| {
             source_file::optimize_sourcefiles(&mut src);
-            let operation = select_operation(ctx, &src, &dst_path);
+            let eol = find_eol(&src[0], &dst_path, &gitattributes_eols);
+            let operation = select_operation(ctx, &src, &dst_path, eol);
             Action {
                 //TODO reduce src (remove useless source) + test
                 //TODO add SourceFile of existing file
@@ -175,43 +303,222 @@ fn plan(ctx: &Ctx, source_files: Vec<SourceFile>, variables: &Variables) -> Resu
     Ok(actions)
 }
 
+/// complete the plan with the files to move (`renamed`) and to delete: the files `removed` or
+/// `recorded` (by the previous apply) but no longer generated, only if they are unmodified
+/// (same md5 as recorded)
+fn plan_upgrades(
+    ctx: &Ctx,
+    mut actions: Vec<Action>,
+    recorded: &BTreeMap<String, String>,
+    removed: &[PathBuf],
+    renamed: &[(PathBuf, PathBuf)],
+) -> Result<Vec<Action>> {
+    let dst_folder = &ctx.cmd_opt.dst_folder;
+    let mut moved = HashSet::new();
+    for (from, to) in renamed {
+        if !dst_folder.join(from).is_file() || fs::symlink_metadata(dst_folder.join(to)).is_ok() {
+            continue;
+        }
+        // the moved file is updated with the new content (like an existing file)
+        for a in actions.iter_mut() {
+            if &a.dst_path.relative == to && a.operation == FileOperation::AddFile {
+                a.operation = FileOperation::UpdateFile;
+            }
+        }
+        actions.push(Action {
+            src: vec![],
+            dst_path: ChildPath {
+                base: dst_folder.clone(),
+                relative: to.clone(),
+            },
+            operation: FileOperation::MoveFile { from: from.clone() },
+        });
+        moved.insert(from.clone());
+    }
+    let generated = actions
+        .iter()
+        .map(|a| a.dst_path.relative.clone())
+        .collect::<HashSet<_>>();
+    let mut candidates = removed.to_vec();
+    candidates.extend(recorded.keys().map(PathBuf::from));
+    candidates.sort();
+    candidates.dedup();
+    for relative in candidates {
+        let path = dst_folder.join(&relative);
+        if generated.contains(&relative) || moved.contains(&relative) || !path.is_file() {
+            continue;
+        }
+        match recorded.get(&record::key_of_path(&relative)) {
+            Some(expected) if expected == &record::digest(&path)? => actions.push(Action {
+                src: vec![],
+                dst_path: ChildPath {
+                    base: dst_folder.clone(),
+                    relative,
+                },
+                operation: FileOperation::DeleteFile,
+            }),
+            Some(_) => warn!(path = ?relative, "not deleted, modified since its generation"),
+            None => warn!(path = ?relative, "not deleted, not recorded as generated"),
+        }
+    }
+    actions.sort_by_key(|a| (a.dst_path.relative.clone(), !a.operation.is_upgrade()));
+    Ok(actions)
+}
+
+/// complete the plan with the files to patch (the patches of each layer by order), an existing
+/// file already patched (and not generated) is not part of the plan
+fn plan_patches(
+    ctx: &Ctx,
+    mut actions: Vec<Action>,
+    layers_patches: Vec<Vec<Patch>>,
+    layers_destinations: &[PathBuf],
+) -> Result<Vec<Action>> {
+    let mut patches_by_dst = BTreeMap::<PathBuf, Vec<Patch>>::new();
+    for (patches, destination) in layers_patches.into_iter().zip(layers_destinations) {
+        for patch in patches {
+            patches_by_dst
+                .entry(destination.join(&patch.path))
+                .or_default()
+                .push(patch);
+        }
+    }
+    for (relative, patches) in patches_by_dst {
+        let dst_path = ChildPath {
+            base: ctx.cmd_opt.dst_folder.clone(),
+            relative,
+        };
+        let path = PathBuf::from(&dst_path);
+        let generated = actions.iter().any(|a| {
+            a.dst_path.relative == dst_path.relative
+                && matches!(
+                    a.operation,
+                    FileOperation::AddFile | FileOperation::UpdateFile
+                )
+        });
+        if !generated && path.is_file() {
+            let current = text::decode(&read_file(&path)?).0;
+            if patches::patch_content(&patches, &current).is_none() {
+                continue;
+            }
+        }
+        actions.push(Action {
+            src: vec![],
+            dst_path,
+            operation: FileOperation::PatchFile { patches },
+        });
+    }
+    actions.sort_by_key(|a| {
+        (
+            a.dst_path.relative.clone(),
+            !a.operation.is_upgrade(),
+            matches!(a.operation, FileOperation::PatchFile { .. }),
+        )
+    });
+    Ok(actions)
+}
+
 //TODO accumulate Result (and error)
-fn execute(ctx: &Ctx, actions: &[Action], variables: &Variables) -> Result<()> {
+/// `engines` have the helpers and the partials of the template,
+/// returns the md5 of the generated files (by relative path), before any merge with the existing files
+fn execute(
+    ctx: &Ctx,
+    actions: &[Action],
+    layers_variables: &[Variables],
+    engines: &Engines,
+) -> Result<BTreeMap<String, String>> {
     use indicatif::ProgressBar;
 
     let pb = ProgressBar::new(actions.len() as u64);
-    let mut handlebars = new_hbs();
-    debug!(?variables, "execute");
-
-    for a in pb.wrap_iter(actions.iter()) {
-        match a.operation {
+    let mut generated = BTreeMap::new();
+    let gitattributes_eols = eol::find_gitattributes_eols(&ctx.cmd_opt.dst_folder)?;
+    let eol_of = |a: &Action| find_eol(&a.src[0], &a.dst_path, &gitattributes_eols);
+    debug!(?layers_variables, "execute");
+
+    // move and delete the previous files before the creation of the new ones,
+    // patch the files after their creation
+    let (upgrades, others): (Vec<_>, Vec<_>) =
+        actions.iter().partition(|a| a.operation.is_upgrade());
+    let (patches, others): (Vec<_>, Vec<_>) = others
+        .into_iter()
+        .partition(|a| matches!(a.operation, FileOperation::PatchFile { .. }));
+    let added = actions
+        .iter()
+        .filter(|a| a.operation == FileOperation::AddFile)
+        .map(|a| a.dst_path.relative.clone())
+        .collect::<HashSet<_>>();
+    for a in pb.wrap_iter(upgrades.into_iter().chain(others).chain(patches)) {
+        match &a.operation {
+            // a raw file is "nothing" only if the destination has the same content (see the plan)
+            FileOperation::Nothing
+                if a.src.len() == 1
+                    && a.src[0].metadata == SourceFileMetadata::RawFile
+                    && PathBuf::from(&a.dst_path).is_file() =>
+            {
+                generated.insert(
+                    record::key_of_path(&a.dst_path.relative),
+                    record::digest(&PathBuf::from(&a.dst_path))?,
+                );
+            }
             FileOperation::Nothing => (),
             FileOperation::Ignore => (),
             // TODO bench performance vs create_dir (and keep create_dir_all for root aka relative is empty)
             FileOperation::MkDir => {
                 let path = PathBuf::from(&a.dst_path);
                 fs::create_dir_all(&path).map_err(|source| Error::CreateFolder { path, source })?;
-                copy_file_permissions(
-                    PathBuf::from(a.src[0].childpath()),
-                    PathBuf::from(&a.dst_path),
-                )?
+                match a.src[0].metadata {
+                    SourceFileMetadata::DeclaredDir { mode: Some(mode) } => {
+                        set_permissions_mode(PathBuf::from(&a.dst_path), mode)?
+                    }
+                    SourceFileMetadata::DeclaredDir { mode: None } => (),
+                    _ => copy_file_permissions(
+                        PathBuf::from(a.src[0].childpath()),
+                        PathBuf::from(&a.dst_path),
+                    )?,
+                }
+            }
+            FileOperation::MkSymlink => mk_symlink_on_action(engines, layers_variables, a)?,
+            FileOperation::MoveFile { from } => {
+                let src = ctx.cmd_opt.dst_folder.join(from);
+                let dst = PathBuf::from(&a.dst_path);
+                if let Some(parent) = dst.parent() {
+                    fs::create_dir_all(parent).map_err(|source| Error::CreateFolder {
+                        path: parent.to_path_buf(),
+                        source,
+                    })?;
+                }
+                fs::rename(&src, &dst).map_err(|source| Error::RenameFile { src, dst, source })?;
+            }
+            FileOperation::DeleteFile => {
+                let path = PathBuf::from(&a.dst_path);
+                fs::remove_file(&path).map_err(|source| Error::RemoveFile { path, source })?;
             }
             FileOperation::AddFile => {
-                mk_file_on_action(&mut handlebars, variables, a, "").map(|_| ())?
+                let (local, _) = mk_file_on_action(engines, layers_variables, a, "", eol_of(a))?;
+                generated.insert(
+                    record::key_of_path(&a.dst_path.relative),
+                    record::digest(&local)?,
+                );
             }
             FileOperation::UpdateFile => {
                 //TODO what to do if .LOCAL, .REMOTE already exist ?
-                let (local, remote) = mk_file_on_action(&mut handlebars, variables, a, ".REMOTE")?;
-                let local_digest =
-                    md5::compute(fs::read(&local).map_err(|source| Error::ReadFile {
-                        path: local.clone(),
-                        source,
-                    })?);
-                let remote_digest =
-                    md5::compute(fs::read(&remote).map_err(|source| Error::ReadFile {
-                        path: remote.clone(),
-                        source,
-                    })?);
+                let eol = eol_of(a);
+                let (local, remote) =
+                    mk_file_on_action(engines, layers_variables, a, ".REMOTE", eol)?;
+                let remote_content = read_file(&remote)?;
+                generated.insert(
+                    record::key_of_path(&a.dst_path.relative),
+                    format!("{:x}", md5::compute(&remote_content)),
+                );
+                // only the managed blocks of the existing file are updated (if any)
+                let remote_content = if managed_blocks::merge_into_remote(&local, &remote)? {
+                    debug!(path = ?local, "managed blocks merged");
+                    read_file(&remote)?
+                } else {
+                    remote_content
+                };
+                // when the line endings are preserved, a difference of line endings is ignored
+                let local_digest = md5::compute(eol::comparable(read_file(&local)?, eol));
+                let remote_digest = md5::compute(eol::comparable(remote_content, eol));
                 if local_digest == remote_digest {
                     fs::remove_file(&remote).map_err(|source| Error::RemoveFile {
                         path: remote.clone(),
@@ -227,98 +534,293 @@ fn execute(ctx: &Ctx, actions: &[Action], variables: &Variables) -> Result<()> {
                     )?
                 }
             }
+            FileOperation::PatchFile { patches } => {
+                let is_added = added.contains(&a.dst_path.relative);
+                if let Some(content) = patch_on_action(ctx, a, patches, is_added)? {
+                    if is_added {
+                        generated.insert(
+                            record::key_of_path(&a.dst_path.relative),
+                            format!("{:x}", md5::compute(&content)),
+                        );
+                    }
+                }
+            }
         }
     }
-    Ok(())
+    Ok(generated)
+}
+
+/// ask (once by command) to run the formatters, the files of a refused formatter are not formatted
+fn confirm_formatters(
+    ctx: &Ctx,
+    actions: Vec<Action>,
+    layers_labels: &[String],
+) -> Result<Vec<Action>> {
+    let mut confirmed: BTreeMap<String, bool> = BTreeMap::new();
+    actions
+        .into_iter()
+        .map(|mut a| {
+            if let Some(cmd) = a.formatter().map(|cmd| cmd.to_owned()) {
+                let run = match confirmed.get(&cmd) {
+                    Some(run) => *run,
+                    None => {
+                        let label = layers_labels
+                            .get(a.src[0].layer_order)
+                            .map(|l| l.as_str())
+                            .unwrap_or_default();
+                        let run = ui::confirm_run_script(ctx, label, &cmd)?;
+                        confirmed.insert(cmd, run);
+                        run
+                    }
+                };
+                if !run {
+                    a.src[0].formatter = None;
+                }
+            }
+            Ok(a)
+        })
+        .collect()
+}
+
+/// the line endings of the generated file, defined by the first of: the rule of `files`,
+/// the `.gitattributes` of the destination, the `eol` of the template (else preserved)
+fn find_eol(
+    source: &SourceFile,
+    dst_path: &ChildPath,
+    gitattributes_eols: &[(PathPattern, Eol)],
+) -> Eol {
+    source
+        .eol
+        .or_else(|| {
+            gitattributes_eols
+                .iter()
+                .find(|(p, _)| p.is_match(&dst_path.relative))
+                .map(|(_, eol)| *eol)
+        })
+        .or(source.template_eol)
+        .unwrap_or_default()
+}
+
+/// insert the snippets of the patches into the file (created if missing), an existing file
+/// (not `is_added` by the plan) is updated like the other files (see `--update-mode`),
+/// returns the patched content (None if every snippet is already present)
+fn patch_on_action(
+    ctx: &Ctx,
+    a: &Action,
+    patches: &[Patch],
+    is_added: bool,
+) -> Result<Option<Vec<u8>>> {
+    let local = PathBuf::from(&a.dst_path);
+    let (current, encoding) = if local.exists() {
+        text::decode(&read_file(&local)?)
+    } else {
+        text::decode(&[])
+    };
+    let content = match patches::patch_content(patches, &current) {
+        Some(patched) => text::encode(&patched, encoding),
+        None => return Ok(None),
+    };
+    let write = |path: &Path| {
+        fs::write(path, &content).map_err(|source| Error::WriteFile {
+            path: path.to_path_buf(),
+            source,
+        })
+    };
+    if is_added || !local.exists() {
+        if let Some(parent) = local.parent().filter(|p| !p.exists()) {
+            fs::create_dir_all(parent).map_err(|source| Error::CreateFolder {
+                path: parent.to_path_buf(),
+                source,
+            })?;
+        }
+        write(&local)?;
+    } else {
+        let remote = files::add_suffix(&local, ".REMOTE")?;
+        write(&remote)?;
+        update_file(&local, &local, &remote, &ctx.cmd_opt.update_mode)?;
+    }
+    Ok(Some(content))
 }
 
 fn mk_file_on_action(
-    handlebars: &mut handlebars::Handlebars,
-    variables: &Variables,
+    engines: &Engines,
+    layers_variables: &[Variables],
     a: &Action,
     dest_suffix_ext: &str,
+    eol: Eol,
 ) -> Result<(PathBuf, PathBuf)> {
-    let mut variables = variables.clone();
     let dest_full_path_target = PathBuf::from(&a.dst_path);
     let dest_full_path = files::add_suffix(&dest_full_path_target, dest_suffix_ext)?;
+    // the folder is not part of the template when the file is renamed (see `files.rename_to`)
+    if let Some(parent) = dest_full_path.parent().filter(|p| !p.exists()) {
+        fs::create_dir_all(parent).map_err(|source| Error::CreateFolder {
+            path: parent.to_path_buf(),
+            source,
+        })?;
+    }
     let mut srcs = a.src.clone();
+    // apply from the last layer to the first one (the root template)
     srcs.reverse();
-    let mut input_content: Vec<u8> = Vec::with_capacity(0);
     let index_latest = srcs.len() - 1;
-    // based of the fact that list of source_files follow one of this configuration
-    // - [RawFile]
-    // - [RenderableFile+,RawFile{0,1}]
+    // the existing file is kept, but completed with the generated content
+    let merge_existing =
+        a.src[0].strategy == FileStrategy::DeepMerge && dest_full_path_target.exists();
+    // the formatter of the first layer (like the strategy)
+    let formatter = a.src[0].formatter.as_deref();
+    let mut content: Vec<u8> = Vec::with_capacity(0);
     for (i, source_file) in srcs.into_iter().enumerate() {
         let src_full_path = PathBuf::from(&source_file.childpath);
         match source_file.metadata {
+            SourceFileMetadata::RawFile
+                if index_latest == 0
+                    && !merge_existing
+                    && eol == Eol::Preserve
+                    && formatter.is_none() =>
+            {
+                fs::copy(&src_full_path, &dest_full_path).map_err(|source| Error::CopyFile {
+                    src: src_full_path.clone(),
+                    dst: dest_full_path.clone(),
+                    source,
+                })?;
+            }
             SourceFileMetadata::RawFile => {
-                if i == index_latest {
-                    fs::copy(&src_full_path, &dest_full_path).map_err(|source| {
-                        Error::CopyFile {
-                            src: src_full_path.clone(),
-                            dst: dest_full_path.clone(),
-                            source,
-                        }
-                    })?;
-                } else {
-                    input_content = fs::read(&src_full_path).map_err(|source| Error::ReadFile {
-                        path: src_full_path.clone(),
-                        source,
-                    })?;
-                }
+                let raw = fs::read(&src_full_path).map_err(|source| Error::ReadFile {
+                    path: src_full_path.clone(),
+                    source,
+                })?;
+                content =
+                    combine_content(&source_file.strategy, &dest_full_path_target, content, raw)?;
             }
             SourceFileMetadata::RenderableFile { .. } => {
-                if i == 0 && dest_full_path_target.exists() {
-                    input_content =
-                        fs::read(&dest_full_path_target).map_err(|source| Error::ReadFile {
-                            path: dest_full_path_target.clone(),
-                            source,
-                        })?;
-                }
-                variables.insert("input_content", String::from_utf8_lossy(&input_content))?;
-                render_template(handlebars, &variables, &src_full_path, &mut input_content)?;
-                if i == index_latest {
-                    fs::write(&dest_full_path, &input_content).map_err(|source| {
-                        Error::WriteFile {
-                            path: dest_full_path.clone(),
-                            source,
-                        }
-                    })?;
+                if i == 0
+                    && source_file.strategy == FileStrategy::RenderChain
+                    && dest_full_path_target.exists()
+                {
+                    content = read_file(&dest_full_path_target)?;
                 }
+                let mut variables = variables_of(&source_file, layers_variables)?;
+                variables.insert("input_content", text::decode(&content).0)?;
+                let mut rendered = Vec::new();
+                render_template(
+                    engines.get(source_file.engine),
+                    &variables,
+                    &src_full_path,
+                    &mut rendered,
+                )?;
+                content = combine_content(
+                    &source_file.strategy,
+                    &dest_full_path_target,
+                    content,
+                    rendered,
+                )?;
+            }
+            SourceFileMetadata::Dir
+            | SourceFileMetadata::DeclaredDir { .. }
+            | SourceFileMetadata::Symlink { .. } => {
+                return Err(Error::FileFromFolder {
+                    src: src_full_path,
+                    dst: dest_full_path_target,
+                })
             }
-            _ => (), // TODO return error,
         }
         if i == index_latest {
+            if merge_existing {
+                let existing = read_file(&dest_full_path_target)?;
+                content = deep_merge::merge(&dest_full_path_target, &content, &existing)?;
+            }
+            if index_latest > 0
+                || merge_existing
+                || eol != Eol::Preserve
+                || formatter.is_some()
+                || source_file.metadata != SourceFileMetadata::RawFile
+            {
+                if let Some(cmd) = formatter.filter(|_| !text::is_binary(&content)) {
+                    content = formatters::format(cmd, &dest_full_path_target, &content)?;
+                }
+                content = eol::convert(content, eol);
+                fs::write(&dest_full_path, &content).map_err(|source| Error::WriteFile {
+                    path: dest_full_path.clone(),
+                    source,
+                })?;
+            }
             copy_file_permissions(&src_full_path, &dest_full_path)?;
         }
     }
     Ok((PathBuf::from(&dest_full_path_target), dest_full_path))
 }
 
+/// create the symlink (replace the existing one), its target is rendered
+fn mk_symlink_on_action(
+    engines: &Engines,
+    layers_variables: &[Variables],
+    a: &Action,
+) -> Result<()> {
+    if let SourceFileMetadata::Symlink { target } = &a.src[0].metadata {
+        let target = if target.contains('{') {
+            engines.get(a.src[0].engine).render(
+                target,
+                &variables_of(&a.src[0], layers_variables)?,
+                &format!("define target of the symlink '{:?}'", a.src[0].childpath),
+            )?
+        } else {
+            target.clone()
+        };
+        let path = PathBuf::from(&a.dst_path);
+        if fs::symlink_metadata(&path).is_ok() {
+            fs::remove_file(&path).map_err(|source| Error::RemoveFile {
+                path: path.clone(),
+                source,
+            })?;
+        }
+        files::create_symlink(Path::new(&target), &path)?;
+    }
+    Ok(())
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|source| Error::ReadFile {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// combine the content of a source with the previous content (from the next layers)
+fn combine_content(
+    strategy: &FileStrategy,
+    dst: &Path,
+    previous: Vec<u8>,
+    current: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let content = match strategy {
+        FileStrategy::Append => [previous, current].concat(),
+        FileStrategy::Prepend => [current, previous].concat(),
+        FileStrategy::DeepMerge if !previous.is_empty() => {
+            deep_merge::merge(dst, &previous, &current)?
+        }
+        FileStrategy::Override
+        | FileStrategy::KeepFirst
+        | FileStrategy::RenderChain
+        | FileStrategy::DeepMerge => current,
+    };
+    Ok(content)
+}
+
+/// render the template (utf-8, utf-16 or latin-1), the output keeps the encoding of the template
 fn render_template(
-    handlebars: &mut handlebars::Handlebars,
+    engine: &dyn Engine,
     variables: &Variables,
     src_full_path: &Path,
     output: &mut Vec<u8>,
 ) -> Result<()> {
     let src_name = &src_full_path.to_string_lossy();
-    handlebars
-        .register_template_file(src_name, src_full_path)
-        .map_err(handlebars::RenderError::from)
-        .map_err(|source| Error::Handlebars {
-            when: format!("load content of template '{:?}'", &src_full_path),
-            template: src_name.to_string(),
-            source,
-        })?;
-    output.clear(); //vec![u8] writer appends content if not clear
-    handlebars
-        .render_to_write(src_name, &variables, output)
-        .map_err(handlebars::RenderError::from)
-        .map_err(|source| Error::Handlebars {
-            when: "render template into buffer".into(),
-            template: src_name.to_string(),
-            source,
-        })?;
+    let (template, encoding) = text::decode(&read_file(src_full_path)?);
+    let rendered = engine.render_named(
+        src_name,
+        &template,
+        variables,
+        "render template into buffer",
+    )?;
+    *output = text::encode(&rendered, encoding);
     Ok(())
 }
 
@@ -344,6 +846,27 @@ where
     Ok(())
 }
 
+/// set the permissions (unix mode) of `path`, ignored on other platforms
+fn set_permissions_mode<P>(path: P, mode: u32) -> Result<()>
+where
+    P: AsRef<std::path::Path>,
+{
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let path = path.as_ref();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(|source| {
+            Error::SetPermissions {
+                path: path.into(),
+                source,
+            }
+        })?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
 fn update_file<P>(src: P, local: P, remote: P, mode_init: &UpdateMode) -> Result<()>
 where
     P: AsRef<std::path::Path>,
@@ -456,8 +979,64 @@ where
     Ok(())
 }
 
-//TODO optimize / bench to avoid re-creation of handlebars at each call
-fn compute_dst_path(ctx: &Ctx, src: &ChildPath, variables: &Variables) -> Result<ChildPath> {
+/// replace a source to generate for each item of a list (see `files.for_each`)
+/// by a source per item, a list provided as a string is parsed (as yaml)
+fn expand_loops(source_files: Vec<SourceFile>, layers_variables: &[Variables]) -> Vec<SourceFile> {
+    let mut back = Vec::with_capacity(source_files.len());
+    for source_file in source_files {
+        let file_loop = match &source_file.for_each {
+            Some(v) if v.item.is_none() => v,
+            _ => {
+                back.push(source_file);
+                continue;
+            }
+        };
+        let value = layers_variables[source_file.layer_order].get(&file_loop.variable);
+        let items = match value {
+            Some(serde_yaml::Value::Sequence(items)) => items.clone(),
+            Some(serde_yaml::Value::String(s)) => match Variables::value_from_str(s) {
+                Ok(serde_yaml::Value::Sequence(items)) => items,
+                _ => vec![serde_yaml::Value::String(s.clone())],
+            },
+            None | Some(serde_yaml::Value::Null) => {
+                debug!(variable = ?file_loop.variable, "no item to generate");
+                vec![]
+            }
+            Some(v) => vec![v.clone()],
+        };
+        for item in items {
+            let mut file_loop = file_loop.clone();
+            file_loop.item = Some(item);
+            back.push(SourceFile {
+                for_each: Some(file_loop),
+                ..source_file.clone()
+            });
+        }
+    }
+    back
+}
+
+/// the variables to render the source: the variables of its layer (and the current item of a loop)
+fn variables_of(source_file: &SourceFile, layers_variables: &[Variables]) -> Result<Variables> {
+    let mut variables = layers_variables[source_file.layer_order].clone();
+    if let Some(source_file::FileLoop {
+        item_name,
+        item: Some(item),
+        ..
+    }) = &source_file.for_each
+    {
+        variables.insert(item_name.as_str(), item)?;
+    }
+    Ok(variables)
+}
+
+/// the path of the destination, `engine` renders the path of the source (if it has expressions)
+fn compute_dst_path(
+    ctx: &Ctx,
+    src: &ChildPath,
+    variables: &Variables,
+    engine: &dyn Engine,
+) -> Result<ChildPath> {
     let rendered_relative = src
         .relative
         .to_str()
@@ -466,14 +1045,7 @@ fn compute_dst_path(ctx: &Ctx, src: &ChildPath, variables: &Variables) -> Result
             let p = if !s.contains('{') {
                 s.to_owned()
             } else {
-                let handlebars = new_hbs();
-                handlebars
-                    .render_template(s, variables)
-                    .map_err(|source| Error::Handlebars {
-                        when: format!("define path for '{:?}'", src),
-                        template: s.into(),
-                        source,
-                    })?
+                engine.render(s, variables, &format!("define path for '{:?}'", src))?
             };
             Ok(PathBuf::from(p))
         })?;
@@ -485,48 +1057,110 @@ fn compute_dst_path(ctx: &Ctx, src: &ChildPath, variables: &Variables) -> Result
     })
 }
 
-fn select_operation(_ctx: &Ctx, sources: &[SourceFile], dst_path: &ChildPath) -> FileOperation {
+/// `eol` is the line endings of the generated file (see `find_eol`)
+fn select_operation(
+    _ctx: &Ctx,
+    sources: &[SourceFile],
+    dst_path: &ChildPath,
+    eol: Eol,
+) -> FileOperation {
     //FIXME to use all the sources
     let src_full_path = PathBuf::from(sources[0].childpath());
     let dest_full_path = PathBuf::from(dst_path);
+    if let SourceFileMetadata::Symlink { .. } = sources[0].metadata {
+        // an existing symlink is replaced, not an existing file or folder
+        return match fs::symlink_metadata(&dest_full_path) {
+            Ok(m) if !m.file_type().is_symlink() => FileOperation::Nothing,
+            _ => FileOperation::MkSymlink,
+        };
+    }
+    let is_dir = src_full_path.is_dir()
+        || matches!(sources[0].metadata, SourceFileMetadata::DeclaredDir { .. });
     if dest_full_path.exists() {
-        if dest_full_path.is_dir() {
+        if dest_full_path.is_dir() || is_dir || is_same_raw_content(sources, &dest_full_path, eol) {
             FileOperation::Nothing
         } else {
             FileOperation::UpdateFile
         }
-    } else if src_full_path.is_dir() {
+    } else if is_dir {
         FileOperation::MkDir
     } else {
         FileOperation::AddFile
     }
 }
 
-fn run_scripts(ctx: &Ctx, template_composite: &TemplateComposite) -> Result<()> {
-    do_in_folder(&ctx.cmd_opt.dst_folder, || {
-        for (loc, scripts) in template_composite.find_scripts()? {
+/// true if the only source is a raw file with the same content (same hash) as `dst`,
+/// and it's copied as is (line endings preserved and no formatter)
+fn is_same_raw_content(sources: &[SourceFile], dst: &Path, eol: Eol) -> bool {
+    match sources {
+        [source]
+            if source.metadata == SourceFileMetadata::RawFile
+                && eol == Eol::Preserve
+                && source.formatter.is_none() =>
+        {
+            match (
+                record::digest(&PathBuf::from(source.childpath())),
+                record::digest(dst),
+            ) {
+                (Ok(src), Ok(dst)) => src == dst,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// run the scripts of each layer into its destination folder
+fn run_scripts(
+    ctx: &Ctx,
+    template_composite: &TemplateComposite,
+    layers_destinations: &[PathBuf],
+) -> Result<()> {
+    for ((loc, scripts), destination) in template_composite
+        .find_scripts()?
+        .into_iter()
+        .zip(layers_destinations)
+    {
+        if scripts.is_empty() {
+            continue;
+        }
+        // the destination of the layer is created by `do_in_folder`,
+        // even when the layer doesn't generate any file into it (eg: a layer with only scripts)
+        do_in_folder(&ctx.cmd_opt.dst_folder.join(destination), || {
             for script in &scripts {
                 if let Some(message) = &script.message {
                     ui::show_message(ctx, loc, message)?;
                 }
                 if let Some(cmd) = &script.cmd {
                     if ui::confirm_run_script(ctx, loc, cmd)? {
-                        if let Err(err) = script.run() {
-                            warn!(?err);
+                        let result = script.run().and_then(|output| {
+                            ui::show_script_output(ctx, loc, &output.stdout, &output.stderr)?;
+                            script.check(&output)
+                        });
+                        if let Err(err) = result {
+                            match script.on_failure {
+                                OnFailure::Abort => return Err(err),
+                                OnFailure::Warn => warn!(?err, "script failed"),
+                                OnFailure::Ignore => debug!(?err, "script failed (ignored)"),
+                            }
                         }
                     }
                 }
             }
-        }
-        Ok(())
-    })
+            Ok(())
+        })?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     pub use crate::cli_opt::*;
+    use crate::engine::EngineKind;
+    use handlebars_misc_helpers::new_hbs;
     use spectral::prelude::*;
+    use std::str::FromStr;
     use tempfile::TempDir;
 
     const DST_FOLDER_STR: &str = "test/dst";
@@ -556,7 +1190,7 @@ mod tests {
         let variables = new_variables_for_test();
         let src = ChildPath::new("test/src", "hello/sample.txt");
         let expected = ChildPath::new(DST_FOLDER_STR, "hello/sample.txt");
-        let actual = compute_dst_path(&ctx, &src, &variables).unwrap();
+        let actual = compute_dst_path(&ctx, &src, &variables, &new_hbs()).unwrap();
         assert_that!(&actual).is_equal_to(&expected);
     }
 
@@ -566,7 +1200,7 @@ mod tests {
         let variables = new_variables_for_test();
         let src = ChildPath::new("test/src", "hello/sample.txt.ffizer.hbs");
         let expected = ChildPath::new(DST_FOLDER_STR, "hello/sample.txt");
-        let actual = compute_dst_path(&ctx, &src, &variables).unwrap();
+        let actual = compute_dst_path(&ctx, &src, &variables, &new_hbs()).unwrap();
         assert_that!(&actual).is_equal_to(&expected);
     }
 
@@ -577,7 +1211,7 @@ mod tests {
 
         let src = ChildPath::new("test/src", "hello/{{ prj }}.txt");
         let expected = ChildPath::new(DST_FOLDER_STR, "hello/myprj.txt");
-        let actual = compute_dst_path(&ctx, &src, &variables).unwrap();
+        let actual = compute_dst_path(&ctx, &src, &variables, &new_hbs()).unwrap();
         assert_that!(&actual).is_equal_to(&expected);
     }
 
@@ -588,7 +1222,7 @@ mod tests {
 
         let src = ChildPath::new("test/src", "hello/{{ prj }}/sample.txt");
         let expected = ChildPath::new(DST_FOLDER_STR, "hello/myprj/sample.txt");
-        let actual = compute_dst_path(&ctx, &src, &variables).unwrap();
+        let actual = compute_dst_path(&ctx, &src, &variables, &new_hbs()).unwrap();
         assert_that!(&actual).is_equal_to(&expected);
     }
 
@@ -607,7 +1241,7 @@ mod tests {
         let variables = new_variables_for_test();
 
         let sources: Vec<SourceFile> = vec![];
-        let actions = plan(&ctx, sources, &variables)?;
+        let actions = plan(&ctx, sources, &[variables], &[PathBuf::new()])?;
         assert_that!(&actions).is_empty();
         Ok(())
     }
@@ -615,13 +1249,13 @@ mod tests {
     #[test]
     fn test_plan_with_duplicate_from_2_templates() -> Result<(), Box<dyn std::error::Error>> {
         let ctx = new_ctx_for_test();
-        let variables = new_variables_for_test();
+        let layers_variables = vec![new_variables_for_test(); 3];
 
         let sources: Vec<SourceFile> = vec![
             SourceFile::from((ChildPath::new("test/src1", "hello/file1.txt"), 1)),
             SourceFile::from((ChildPath::new("test/src2", "hello/file1.txt"), 2)),
         ];
-        let actions = plan(&ctx, sources, &variables)?;
+        let actions = plan(&ctx, sources, &layers_variables, &vec![PathBuf::new(); 3])?;
         let expected = vec![Action {
             src: vec![SourceFile::from((
                 ChildPath::new("test/src1", "hello/file1.txt"),
@@ -634,6 +1268,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_plan_with_strategies_from_3_templates() -> Result<(), Box<dyn std::error::Error>> {
+        let ctx = new_ctx_for_test();
+        let layers_variables = vec![new_variables_for_test(); 3];
+        let mut first = SourceFile::from((ChildPath::new("test/src0", "hello/file1.txt"), 0));
+        first.strategy = FileStrategy::Append;
+        let mut second = SourceFile::from((ChildPath::new("test/src1", "hello/file1.txt"), 1));
+        second.strategy = FileStrategy::KeepFirst;
+        let third = SourceFile::from((ChildPath::new("test/src2", "hello/file1.txt"), 2));
+
+        let sources: Vec<SourceFile> = vec![third.clone(), second, first.clone()];
+        let actions = plan(&ctx, sources, &layers_variables, &vec![PathBuf::new(); 3])?;
+        let expected = vec![Action {
+            src: vec![first, third],
+            dst_path: ChildPath::new(DST_FOLDER_STR, "hello/file1.txt"),
+            operation: FileOperation::AddFile,
+        }];
+        assert_that!(&actions).is_equal_to(&expected);
+        Ok(())
+    }
+
     #[cfg(unix)]
     #[test]
     fn test_copy_file_permissions() {
@@ -664,6 +1319,21 @@ mod tests {
             .is_equal_to(fs::metadata(&src_path).unwrap().permissions());
     }
 
+    #[test]
+    fn test_find_eol_by_file_then_gitattributes_then_template() {
+        let mut src = SourceFile::from((ChildPath::new("/tmp/template", "run.bat"), 0));
+        src.template_eol = Some(Eol::Lf);
+        let dst_path = ChildPath::new("/tmp/dst", "run.bat");
+        let gitattributes_eols = vec![(PathPattern::from_str("*.bat").unwrap(), Eol::Crlf)];
+        assert_that!(find_eol(&src, &dst_path, &[])).is_equal_to(Eol::Lf);
+        assert_that!(find_eol(&src, &dst_path, &gitattributes_eols)).is_equal_to(Eol::Crlf);
+        src.eol = Some(Eol::Native);
+        assert_that!(find_eol(&src, &dst_path, &gitattributes_eols)).is_equal_to(Eol::Native);
+        src.eol = None;
+        src.template_eol = None;
+        assert_that!(find_eol(&src, &dst_path, &[])).is_equal_to(Eol::Preserve);
+    }
+
     #[test]
     fn test_mk_file_by_copy() {
         // Create a directory inside of `std::env::temp_dir()`
@@ -682,10 +1352,11 @@ mod tests {
             operation: FileOperation::AddFile,
         };
 
-        let mut handlebars = new_hbs();
+        let engines = Engines::default();
         let variables = new_variables_for_test();
 
-        mk_file_on_action(&mut handlebars, &variables, &action, "").expect("mk_file is ok");
+        mk_file_on_action(&engines, &[variables], &action, "", Eol::Preserve)
+            .expect("mk_file is ok");
         assert_that!(&dst_path).exists();
         assert_that!(fs::read_to_string(&dst_path).unwrap()).is_equal_to(CONTENT_BASE.to_owned());
         assert_that!(fs::metadata(&dst_path).unwrap().permissions())
@@ -710,16 +1381,555 @@ mod tests {
             operation: FileOperation::AddFile,
         };
 
-        let mut handlebars = new_hbs();
+        let engines = Engines::default();
         let variables = new_variables_for_test();
 
-        mk_file_on_action(&mut handlebars, &variables, &action, "").expect("mk_file is ok");
+        mk_file_on_action(&engines, &[variables], &action, "", Eol::Preserve)
+            .expect("mk_file is ok");
         assert_that!(&dst_path).exists();
         assert_that!(fs::read_to_string(&dst_path).unwrap()).is_equal_to(CONTENT_REMOTE.to_owned());
         assert_that!(fs::metadata(&dst_path).unwrap().permissions())
             .is_equal_to(fs::metadata(&src_path).unwrap().permissions());
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_mk_file_with_formatter() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+
+        let src = ChildPath::new(tmp_dir.path(), "src.txt");
+        fs::write(PathBuf::from(&src), "hello\r\n").expect("create src file");
+
+        let dst = ChildPath::new(tmp_dir.path(), "dst.txt");
+        let dst_path = PathBuf::from(&dst);
+
+        let action = Action {
+            dst_path: dst,
+            src: vec![SourceFile {
+                formatter: Some("tr a-z A-Z".to_owned()),
+                ..SourceFile::from((src, 0))
+            }],
+            operation: FileOperation::AddFile,
+        };
+
+        mk_file_on_action(
+            &Engines::default(),
+            &[new_variables_for_test()],
+            &action,
+            "",
+            Eol::Lf,
+        )
+        .expect("mk_file is ok");
+        assert_that!(fs::read_to_string(&dst_path).unwrap()).is_equal_to("HELLO\n".to_owned());
+
+        let failing = Action {
+            src: vec![SourceFile {
+                formatter: Some("exit 3".to_owned()),
+                ..action.src[0].clone()
+            }],
+            ..action
+        };
+        let actual = mk_file_on_action(
+            &Engines::default(),
+            &[new_variables_for_test()],
+            &failing,
+            "",
+            Eol::Lf,
+        );
+        assert!(matches!(actual, Err(Error::Format { .. })));
+    }
+
+    #[test]
+    fn test_mk_file_by_strategies_of_layers() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let mk_src = |layer: &str, name: &str, content: &str, strategy: FileStrategy| {
+            let src = ChildPath::new(tmp_dir.path().join(layer), name);
+            fs::create_dir_all(tmp_dir.path().join(layer)).expect("create layer folder");
+            fs::write(PathBuf::from(&src), content).expect("create src file");
+            let mut source_file = SourceFile::from((src, layer.len()));
+            source_file.strategy = strategy;
+            source_file
+        };
+        let dst = ChildPath::new(tmp_dir.path(), "dst.txt");
+        let dst_path = PathBuf::from(&dst);
+        let action = Action {
+            dst_path: dst,
+            src: vec![
+                mk_src("0", "dst.txt", "first\n", FileStrategy::Prepend),
+                mk_src(
+                    "01",
+                    "dst.txt.ffizer.hbs",
+                    "{{ base }}\n",
+                    FileStrategy::Append,
+                ),
+                mk_src("012", "dst.txt", "base\n", FileStrategy::Override),
+            ],
+            operation: FileOperation::AddFile,
+        };
+
+        let engines = Engines::default();
+        let layers_variables = vec![new_variables_for_test(); 4];
+
+        mk_file_on_action(&engines, &layers_variables, &action, "", Eol::Preserve)
+            .expect("mk_file is ok");
+        assert_that!(fs::read_to_string(&dst_path).unwrap())
+            .is_equal_to("first\nbase\nremote\n".to_owned());
+    }
+
+    #[test]
+    fn test_mk_file_by_deep_merge_with_existing() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let src = ChildPath::new(tmp_dir.path(), "src.json");
+        fs::write(
+            PathBuf::from(&src),
+            r#"{"name": "remote", "scripts": {"test": "jest"}}"#,
+        )
+        .expect("create src file");
+        let dst = ChildPath::new(tmp_dir.path(), "dst.json");
+        fs::write(PathBuf::from(&dst), r#"{"name": "local"}"#).expect("create dst file");
+
+        let mut source_file = SourceFile::from((src, 0));
+        source_file.strategy = FileStrategy::DeepMerge;
+        let action = Action {
+            dst_path: dst,
+            src: vec![source_file],
+            operation: FileOperation::UpdateFile,
+        };
+        let engines = Engines::default();
+        let variables = new_variables_for_test();
+
+        let (_, remote) =
+            mk_file_on_action(&engines, &[variables], &action, ".REMOTE", Eol::Preserve)
+                .expect("mk_file is ok");
+        assert_that!(fs::read_to_string(remote).unwrap()).is_equal_to(
+            "{\n  \"name\": \"local\",\n  \"scripts\": {\n    \"test\": \"jest\"\n  }\n}\n"
+                .to_owned(),
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_plan_and_execute_symlink() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let src_folder = tmp_dir.path().join("src");
+        fs::create_dir_all(&src_folder).expect("create src folder");
+        fs::write(src_folder.join("myprj.txt"), CONTENT_BASE).expect("create src file");
+        std::os::unix::fs::symlink("{{ prj }}.txt", src_folder.join("latest"))
+            .expect("create src symlink");
+        let ctx = Ctx {
+            cmd_opt: ApplyOpts {
+                dst_folder: tmp_dir.path().join("dst"),
+                ..Default::default()
+            },
+        };
+        fs::create_dir_all(&ctx.cmd_opt.dst_folder).expect("create dst folder");
+        let source_file = SourceFile::new(ChildPath::new(&src_folder, "latest"), 0, false);
+        assert_that!(&source_file.metadata).is_equal_to(&SourceFileMetadata::Symlink {
+            target: "{{ prj }}.txt".to_owned(),
+        });
+        let followed = SourceFile::new(ChildPath::new(&src_folder, "latest"), 0, true);
+        assert_that!(&followed.metadata).is_equal_to(&SourceFileMetadata::RawFile);
+
+        let layers_variables = vec![new_variables_for_test()];
+        let actions = plan(
+            &ctx,
+            vec![source_file],
+            &layers_variables,
+            &[PathBuf::new()],
+        )
+        .expect("plan is ok");
+        assert_that!(&actions[0].operation).is_equal_to(&FileOperation::MkSymlink);
+        execute(&ctx, &actions, &layers_variables, &Engines::default()).expect("execute is ok");
+        let dst_path = ctx.cmd_opt.dst_folder.join("latest");
+        assert_that!(fs::read_link(&dst_path).unwrap()).is_equal_to(PathBuf::from("myprj.txt"));
+
+        // an existing symlink is replaced
+        let actions = plan(
+            &ctx,
+            vec![SourceFile::new(
+                ChildPath::new(&src_folder, "latest"),
+                0,
+                false,
+            )],
+            &layers_variables,
+            &[PathBuf::new()],
+        )
+        .expect("plan is ok");
+        execute(&ctx, &actions, &layers_variables, &Engines::default()).expect("execute is ok");
+        assert_that!(fs::read_link(&dst_path).unwrap()).is_equal_to(PathBuf::from("myprj.txt"));
+    }
+
+    #[test]
+    fn test_plan_and_execute_declared_dir() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let ctx = Ctx {
+            cmd_opt: ApplyOpts {
+                dst_folder: tmp_dir.path().join("dst"),
+                ..Default::default()
+            },
+        };
+        let source_file = SourceFile {
+            childpath: ChildPath::new(tmp_dir.path().join("src"), "data/{{ prj }}"),
+            layer_order: 0,
+            metadata: SourceFileMetadata::DeclaredDir { mode: Some(0o750) },
+            strategy: FileStrategy::Override,
+            rename_to: None,
+            for_each: None,
+            eol: None,
+            template_eol: None,
+            formatter: None,
+            engine: EngineKind::default(),
+        };
+        let layers_variables = vec![new_variables_for_test()];
+        let actions = plan(
+            &ctx,
+            vec![source_file],
+            &layers_variables,
+            &[PathBuf::new()],
+        )
+        .expect("plan is ok");
+        assert_that!(&actions[0].operation).is_equal_to(&FileOperation::MkDir);
+        assert_that!(&actions[0].dst_path)
+            .is_equal_to(ChildPath::new(tmp_dir.path().join("dst"), "data/myprj"));
+        execute(&ctx, &actions, &layers_variables, &Engines::default()).expect("execute is ok");
+        let dst_path = tmp_dir.path().join("dst").join("data").join("myprj");
+        assert_that!(&dst_path).is_a_directory();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_that!(fs::metadata(&dst_path).unwrap().permissions().mode() & 0o777)
+                .is_equal_to(0o750);
+        }
+    }
+
+    #[test]
+    fn test_plan_and_execute_upgrades() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let dst_folder = tmp_dir.path().join("dst");
+        let ctx = Ctx {
+            cmd_opt: ApplyOpts {
+                dst_folder: dst_folder.clone(),
+                ..Default::default()
+            },
+        };
+        fs::create_dir_all(dst_folder.join("doc")).unwrap();
+        let mut recorded = BTreeMap::new();
+        for (name, content) in [
+            ("unchanged.txt", "v1"),
+            ("modified.txt", "v1"),
+            ("doc/old.md", "v1"),
+        ] {
+            let path = dst_folder.join(name);
+            fs::write(&path, content).unwrap();
+            recorded.insert(name.to_owned(), record::digest(&path).unwrap());
+        }
+        fs::write(dst_folder.join("modified.txt"), "v1 modified").unwrap();
+        fs::write(dst_folder.join("not_recorded.txt"), "v1").unwrap();
+        let src_path = tmp_dir.path().join("src");
+        fs::create_dir_all(src_path.join("doc")).unwrap();
+        fs::write(src_path.join("doc").join("new.md"), "v2").unwrap();
+        let source_file = SourceFile::from((ChildPath::new(&src_path, "doc/new.md"), 0));
+        let layers_variables = vec![new_variables_for_test()];
+        let actions = plan(
+            &ctx,
+            vec![source_file],
+            &layers_variables,
+            &[PathBuf::new()],
+        )
+        .expect("plan is ok");
+        let actions = plan_upgrades(
+            &ctx,
+            actions,
+            &recorded,
+            &[PathBuf::from("not_recorded.txt")],
+            &[(PathBuf::from("doc/old.md"), PathBuf::from("doc/new.md"))],
+        )
+        .expect("plan_upgrades is ok");
+        let operations = actions
+            .iter()
+            .map(|a| (a.dst_path.relative.clone(), a.operation.clone()))
+            .collect::<Vec<_>>();
+        assert_that!(&operations).is_equal_to(vec![
+            (
+                PathBuf::from("doc/new.md"),
+                FileOperation::MoveFile {
+                    from: PathBuf::from("doc/old.md"),
+                },
+            ),
+            (PathBuf::from("doc/new.md"), FileOperation::UpdateFile),
+            (PathBuf::from("unchanged.txt"), FileOperation::DeleteFile),
+        ]);
+        let ctx = Ctx {
+            cmd_opt: ApplyOpts {
+                update_mode: UpdateMode::Override,
+                ..ctx.cmd_opt
+            },
+        };
+        let generated =
+            execute(&ctx, &actions, &layers_variables, &Engines::default()).expect("execute is ok");
+        assert_that!(&dst_folder.join("unchanged.txt")).does_not_exist();
+        assert_that!(&dst_folder.join("doc").join("old.md")).does_not_exist();
+        assert_that!(&dst_folder.join("modified.txt")).exists();
+        assert_that!(&dst_folder.join("not_recorded.txt")).exists();
+        assert_that!(fs::read_to_string(dst_folder.join("doc").join("new.md")).unwrap())
+            .is_equal_to("v2".to_owned());
+        assert_that!(generated.into_keys().collect::<Vec<_>>())
+            .is_equal_to(vec!["doc/new.md".to_owned()]);
+    }
+
+    #[test]
+    fn test_plan_and_execute_patches() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let dst_folder = tmp_dir.path().join("dst");
+        fs::create_dir_all(&dst_folder).unwrap();
+        fs::write(dst_folder.join(".gitignore"), "/target\n").unwrap();
+        fs::write(dst_folder.join("patched.txt"), "foo\n").unwrap();
+        let src_path = tmp_dir.path().join("src");
+        fs::create_dir_all(&src_path).unwrap();
+        fs::write(src_path.join("new.txt"), "new\n").unwrap();
+        let ctx = Ctx {
+            cmd_opt: ApplyOpts {
+                dst_folder: dst_folder.clone(),
+                update_mode: UpdateMode::Keep,
+                ..Default::default()
+            },
+        };
+        let layers_variables = vec![new_variables_for_test()];
+        let actions = plan(
+            &ctx,
+            vec![SourceFile::from((ChildPath::new(&src_path, "new.txt"), 0))],
+            &layers_variables,
+            &[PathBuf::new()],
+        )
+        .expect("plan is ok");
+        let patch = |path: &str| Patch::new(path, "foo", None, None).unwrap();
+        let actions = plan_patches(
+            &ctx,
+            actions,
+            vec![vec![
+                patch(".gitignore"),
+                patch("new.txt"),
+                patch("patched.txt"),
+            ]],
+            &[PathBuf::new()],
+        )
+        .expect("plan_patches is ok");
+        let operations = actions
+            .iter()
+            .map(|a| (a.dst_path.relative.clone(), a.operation.clone()))
+            .collect::<Vec<_>>();
+        assert_that!(&operations).is_equal_to(vec![
+            (
+                PathBuf::from(".gitignore"),
+                FileOperation::PatchFile {
+                    patches: vec![patch(".gitignore")],
+                },
+            ),
+            (PathBuf::from("new.txt"), FileOperation::AddFile),
+            (
+                PathBuf::from("new.txt"),
+                FileOperation::PatchFile {
+                    patches: vec![patch("new.txt")],
+                },
+            ),
+        ]);
+        // the existing files are updated with the update mode, not the generated ones
+        let generated =
+            execute(&ctx, &actions, &layers_variables, &Engines::default()).expect("execute is ok");
+        assert_that!(fs::read_to_string(dst_folder.join(".gitignore")).unwrap())
+            .is_equal_to("/target\n".to_owned());
+        assert_that!(fs::read_to_string(dst_folder.join("new.txt")).unwrap())
+            .is_equal_to("new\nfoo\n".to_owned());
+        assert_that!(generated.get("new.txt").cloned())
+            .is_equal_to(Some(record::digest(&dst_folder.join("new.txt")).unwrap()));
+        let ctx = Ctx {
+            cmd_opt: ApplyOpts {
+                update_mode: UpdateMode::Override,
+                ..ctx.cmd_opt
+            },
+        };
+        execute(&ctx, &actions[..1], &layers_variables, &Engines::default())
+            .expect("execute is ok");
+        assert_that!(fs::read_to_string(dst_folder.join(".gitignore")).unwrap())
+            .is_equal_to("/target\nfoo\n".to_owned());
+        assert_that!(&dst_folder.join(".gitignore.REMOTE")).does_not_exist();
+    }
+
+    #[test]
+    fn test_execute_managed_blocks_with_update_mode() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let dst_folder = tmp_dir.path().join("dst");
+        let src_path = tmp_dir.path().join("src");
+        fs::create_dir_all(&dst_folder).unwrap();
+        fs::create_dir_all(&src_path).unwrap();
+        let local = "custom\n# ffizer:begin x\nv1\n# ffizer:end x\n";
+        fs::write(
+            src_path.join("Makefile"),
+            "# ffizer:begin x\nv2\n# ffizer:end x\n",
+        )
+        .unwrap();
+        let layers_variables = vec![new_variables_for_test()];
+        let source_file = SourceFile::from((ChildPath::new(&src_path, "Makefile"), 0));
+        for (update_mode, expected_local, expected_remote) in [
+            (UpdateMode::Keep, local, None),
+            (
+                UpdateMode::UpdateAsRemote,
+                local,
+                Some("custom\n# ffizer:begin x\nv2\n# ffizer:end x\n"),
+            ),
+            (
+                UpdateMode::Override,
+                "custom\n# ffizer:begin x\nv2\n# ffizer:end x\n",
+                None,
+            ),
+        ] {
+            fs::write(dst_folder.join("Makefile"), local).unwrap();
+            let _ = fs::remove_file(dst_folder.join("Makefile.REMOTE"));
+            let ctx = Ctx {
+                cmd_opt: ApplyOpts {
+                    dst_folder: dst_folder.clone(),
+                    update_mode,
+                    ..Default::default()
+                },
+            };
+            let actions = plan(
+                &ctx,
+                vec![source_file.clone()],
+                &layers_variables,
+                &[PathBuf::new()],
+            )
+            .expect("plan is ok");
+            assert_that!(&actions[0].operation).is_equal_to(&FileOperation::UpdateFile);
+            execute(&ctx, &actions, &layers_variables, &Engines::default()).expect("execute is ok");
+            assert_that!(fs::read_to_string(dst_folder.join("Makefile")).unwrap())
+                .is_equal_to(expected_local.to_owned());
+            assert_that!(fs::read_to_string(dst_folder.join("Makefile.REMOTE")).ok())
+                .is_equal_to(expected_remote.map(|s| s.to_owned()));
+        }
+    }
+
+    #[test]
+    fn test_plan_for_each_item() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let ctx = Ctx {
+            cmd_opt: ApplyOpts {
+                dst_folder: tmp_dir.path().join("dst"),
+                ..Default::default()
+            },
+        };
+        let source_file = SourceFile {
+            for_each: Some(source_file::FileLoop {
+                variable: "services".to_owned(),
+                item_name: "service".to_owned(),
+                item: None,
+            }),
+            ..SourceFile::from((
+                ChildPath::new(tmp_dir.path().join("src"), "{{ service }}.txt"),
+                0,
+            ))
+        };
+        let mut variables = new_variables_for_test();
+        variables
+            .insert("services", vec!["api", "worker"])
+            .expect("insert services");
+        let actions =
+            plan(&ctx, vec![source_file], &[variables], &[PathBuf::new()]).expect("plan is ok");
+        let dst_paths = actions
+            .iter()
+            .map(|a| a.dst_path.relative.clone())
+            .collect::<Vec<_>>();
+        assert_that!(&dst_paths)
+            .is_equal_to(vec![PathBuf::from("api.txt"), PathBuf::from("worker.txt")]);
+    }
+
+    #[test]
+    fn test_render_template_keeps_encoding() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let src_path = tmp_dir.path().join("latin1.txt.ffizer.hbs");
+        fs::write(&src_path, b"caf\xe9 {{ prj }}").unwrap();
+        let mut output = vec![];
+        render_template(
+            &new_hbs(),
+            &new_variables_for_test(),
+            &src_path,
+            &mut output,
+        )
+        .expect("render is ok");
+        assert_that!(output).is_equal_to(b"caf\xe9 myprj".to_vec());
+    }
+
+    #[test]
+    fn test_plan_binary_with_same_content() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let ctx = Ctx {
+            cmd_opt: ApplyOpts {
+                dst_folder: tmp_dir.path().join("dst"),
+                ..Default::default()
+            },
+        };
+        fs::create_dir_all(tmp_dir.path().join("src")).unwrap();
+        fs::create_dir_all(tmp_dir.path().join("dst")).unwrap();
+        let content = b"\x1f\x8b\x08\x00\xff";
+        fs::write(tmp_dir.path().join("src").join("ex.gz"), content).unwrap();
+        fs::write(tmp_dir.path().join("dst").join("ex.gz"), content).unwrap();
+        let source_file =
+            SourceFile::from((ChildPath::new(tmp_dir.path().join("src"), "ex.gz"), 0));
+        let actions = plan(
+            &ctx,
+            vec![source_file],
+            &[new_variables_for_test()],
+            &[PathBuf::new()],
+        )
+        .expect("plan is ok");
+        assert_that!(&actions[0].operation).is_equal_to(&FileOperation::Nothing);
+    }
+
+    #[test]
+    fn test_plan_raw_with_same_content_and_eol() {
+        let tmp_dir = TempDir::new().expect("create a temp dir");
+        let ctx = Ctx {
+            cmd_opt: ApplyOpts {
+                dst_folder: tmp_dir.path().join("dst"),
+                ..Default::default()
+            },
+        };
+        fs::create_dir_all(tmp_dir.path().join("src")).unwrap();
+        fs::create_dir_all(tmp_dir.path().join("dst")).unwrap();
+        fs::write(tmp_dir.path().join("src").join("run.bat"), "echo\n").unwrap();
+        fs::write(tmp_dir.path().join("dst").join("run.bat"), "echo\n").unwrap();
+        let source_file =
+            SourceFile::from((ChildPath::new(tmp_dir.path().join("src"), "run.bat"), 0));
+        let operation_of = |source_file: &SourceFile| {
+            plan(
+                &ctx,
+                vec![source_file.clone()],
+                &[new_variables_for_test()],
+                &[PathBuf::new()],
+            )
+            .expect("plan is ok")[0]
+                .operation
+                .clone()
+        };
+        assert_that!(operation_of(&source_file)).is_equal_to(FileOperation::Nothing);
+        // the line endings of the copy could differ from the existing file
+        let with_eol = SourceFile {
+            eol: Some(Eol::Crlf),
+            ..source_file.clone()
+        };
+        assert_that!(operation_of(&with_eol)).is_equal_to(FileOperation::UpdateFile);
+        let with_formatter = SourceFile {
+            formatter: Some("cat".to_owned()),
+            ..source_file.clone()
+        };
+        assert_that!(operation_of(&with_formatter)).is_equal_to(FileOperation::UpdateFile);
+        fs::write(
+            tmp_dir.path().join("dst").join(".gitattributes"),
+            "*.bat eol=crlf\n",
+        )
+        .unwrap();
+        assert_that!(operation_of(&source_file)).is_equal_to(FileOperation::UpdateFile);
+    }
+
     fn setup_for_test_update() -> (TempDir, PathBuf, PathBuf, PathBuf) {
         // Create a directory inside of `std::env::temp_dir()`
         let tmp_dir = TempDir::new().expect("create a temp dir");
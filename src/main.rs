@@ -6,6 +6,7 @@ use ffizer::Command;
 use ffizer::Ctx;
 use ffizer::SourceLoc;
 use ffizer::TestSamplesOpts;
+use ffizer::UserCfg;
 use std::error::Error;
 use tracing::{debug, error, info, trace};
 use tracing_error::ErrorLayer;
@@ -73,6 +74,10 @@ fn inspect() -> Result<(), Box<dyn Error>> {
         "remote cache folder: {}",
         SourceLoc::find_remote_cache_folder()?.to_string_lossy()
     );
+    println!(
+        "user configuration file: {}",
+        UserCfg::find_user_cfg_file()?.to_string_lossy()
+    );
     Ok(())
 }
 
@@ -104,6 +109,7 @@ fn main() {
         Command::ShowJsonSchema => show_json_schema(),
         Command::TestSamples(g) => test_samples(g),
     };
+    SourceLoc::remove_snapshots();
     if let Err(e) = r {
         error!("cmd: {:#?}", &cli_opts);
         error!("failed: {:#?}", &e);
@@ -0,0 +1,173 @@
+//! managed blocks: the lines between `ffizer:begin <id>` and `ffizer:end <id>`
+//! (usually into comments, eg `# ffizer:begin deps`) are owned by the template,
+//! on update only those lines are replaced, the rest of the existing file is preserved.
+use crate::error::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+lazy_static! {
+    static ref MARKER: Regex = Regex::new(r"ffizer:(begin|end)\s+(\S+)").expect("valid regex");
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Block {
+    id: String,
+    /// from the start of the begin marker's line to the end of the end marker's line
+    outer: Range<usize>,
+    /// the lines between the markers
+    inner: Range<usize>,
+}
+
+fn find_blocks(content: &str) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut opened: Option<(String, usize, usize)> = None;
+    let mut start = 0;
+    for line in content.split_inclusive('\n') {
+        let line_start = start;
+        start += line.len();
+        if let Some(c) = MARKER.captures(line) {
+            let id = c[2].trim_end_matches("-->").to_owned();
+            match (&c[1], &opened) {
+                ("begin", _) => opened = Some((id, line_start, start)),
+                ("end", Some((begin_id, _, _))) if begin_id == &id => {
+                    if let Some((id, outer_start, inner_start)) = opened.take() {
+                        blocks.push(Block {
+                            id,
+                            outer: outer_start..start,
+                            inner: inner_start..line_start,
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    blocks
+}
+
+/// replace the managed blocks of `local` by the ones of `remote`,
+/// the blocks missing into `local` are added at the end.
+/// None if `remote` has no managed block.
+fn update_blocks(local: &str, remote: &str) -> Option<String> {
+    let remote_blocks = find_blocks(remote);
+    if remote_blocks.is_empty() {
+        return None;
+    }
+    let local_blocks = find_blocks(local);
+    let mut replacements = vec![];
+    let mut appended = String::new();
+    for block in remote_blocks {
+        match local_blocks.iter().find(|b| b.id == block.id) {
+            Some(local_block) => {
+                replacements.push((local_block.inner.clone(), &remote[block.inner]))
+            }
+            None => appended.push_str(&remote[block.outer]),
+        }
+    }
+    let mut updated = local.to_owned();
+    replacements.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    for (range, content) in replacements {
+        updated.replace_range(range, content);
+    }
+    if !appended.is_empty() {
+        if !updated.is_empty() && !updated.ends_with('\n') {
+            updated.push('\n');
+        }
+        updated.push_str(&appended);
+    }
+    Some(updated)
+}
+
+/// if the generated file (`remote`) has managed blocks, replace its content by `local` with the
+/// managed blocks updated (so the update of `local` by `remote` only changes the managed blocks),
+/// returns false (and do nothing) if `remote` has no managed block (or is not a text file)
+pub(crate) fn merge_into_remote(local: &Path, remote: &Path) -> Result<bool> {
+    let remote_content = match fs::read_to_string(remote) {
+        Ok(v) => v,
+        Err(_) => return Ok(false),
+    };
+    let local_content = match fs::read_to_string(local) {
+        Ok(v) => v,
+        Err(_) => return Ok(false),
+    };
+    match update_blocks(&local_content, &remote_content) {
+        None => Ok(false),
+        Some(updated) => {
+            fs::write(remote, updated).map_err(|source| Error::WriteFile {
+                path: remote.to_path_buf(),
+                source,
+            })?;
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const REMOTE: &str = "\
+all: build
+# ffizer:begin targets
+build:
+\tcargo build --release
+# ffizer:end targets
+# ffizer:begin lint
+lint:
+\tcargo clippy
+# ffizer:end lint
+";
+
+    #[test]
+    fn test_find_blocks() {
+        let blocks = find_blocks("a\n<!-- ffizer:begin x -->\nb\n<!-- ffizer:end x -->\nc\n");
+        assert_eq!(
+            blocks,
+            vec![Block {
+                id: "x".to_owned(),
+                outer: 2..50,
+                inner: 26..28,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_update_blocks_preserve_outside() {
+        let local = "\
+all: build test
+# ffizer:begin targets
+build:
+\tcargo build
+# ffizer:end targets
+
+test:
+\tcargo test";
+        assert_eq!(
+            update_blocks(local, REMOTE).unwrap(),
+            "\
+all: build test
+# ffizer:begin targets
+build:
+\tcargo build --release
+# ffizer:end targets
+
+test:
+\tcargo test
+# ffizer:begin lint
+lint:
+\tcargo clippy
+# ffizer:end lint
+"
+        );
+    }
+
+    #[test]
+    fn test_update_blocks_without_blocks() {
+        assert_eq!(update_blocks("local", "remote"), None);
+        assert_eq!(update_blocks(REMOTE, REMOTE).unwrap(), REMOTE);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::error::*;
+use regex::Regex;
+use std::path::{Component, PathBuf};
+
+/// where to insert the snippet of a patch
+#[derive(Debug, Clone)]
+pub enum Anchor {
+    /// after the first line matching
+    After(Regex),
+    /// before the first line matching
+    Before(Regex),
+    /// at the end of the file
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    /// path of the file to patch (relative to the destination folder of the layer)
+    pub path: PathBuf,
+    pub content: String,
+    pub anchor: Anchor,
+}
+
+impl Patch {
+    pub(crate) fn new(
+        path: &str,
+        content: &str,
+        after: Option<&str>,
+        before: Option<&str>,
+    ) -> Result<Patch> {
+        let invalid = |msg: String| Error::InvalidPatch {
+            path: path.to_owned(),
+            msg,
+        };
+        let to_regex = |v: &str| Regex::new(v).map_err(|e| invalid(e.to_string()));
+        let anchor = match (after, before) {
+            (Some(_), Some(_)) => {
+                return Err(invalid(
+                    "define only one of `after` and `before`".to_owned(),
+                ))
+            }
+            (Some(after), None) => Anchor::After(to_regex(after)?),
+            (None, Some(before)) => Anchor::Before(to_regex(before)?),
+            (None, None) => Anchor::End,
+        };
+        let path = PathBuf::from(path.trim());
+        if path.as_os_str().is_empty()
+            || !path.components().all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(invalid(
+                "the path should be relative (inside the destination folder)".to_owned(),
+            ));
+        }
+        Ok(Patch {
+            path,
+            content: content.to_owned(),
+            anchor,
+        })
+    }
+
+    /// the content with the snippet inserted, None if the snippet is already present at the anchor
+    fn insert_into(&self, current: &str) -> Option<String> {
+        let snippet = self.content.trim_end_matches(['\r', '\n']);
+        if snippet.is_empty() {
+            return None;
+        }
+        let find_line = |re: &Regex| {
+            let mut start = 0;
+            current.split_inclusive('\n').find_map(|line| {
+                let line_start = start;
+                start += line.len();
+                re.is_match(line.trim_end_matches(['\r', '\n']))
+                    .then_some((line_start, start))
+            })
+        };
+        let (offset, after_anchor) = match &self.anchor {
+            Anchor::After(re) => find_line(re).map(|(_, end)| (end, true)),
+            Anchor::Before(re) => find_line(re).map(|(start, _)| (start, false)),
+            Anchor::End => None,
+        }
+        .unwrap_or((current.len(), false));
+        let (head, tail) = current.split_at(offset);
+        if is_present(snippet, head, tail, after_anchor) {
+            return None;
+        }
+        let separator = if head.is_empty() || head.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        Some(format!("{}{}{}\n{}", head, separator, snippet, tail))
+    }
+}
+
+impl PartialEq for Anchor {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Anchor::After(a), Anchor::After(b)) | (Anchor::Before(a), Anchor::Before(b)) => {
+                a.as_str() == b.as_str()
+            }
+            (Anchor::End, Anchor::End) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Anchor {}
+
+/// the content with the snippets of the patches inserted (by order), None if every snippet is
+/// already present at its anchor
+pub(crate) fn patch_content(patches: &[Patch], current: &str) -> Option<String> {
+    patches.iter().fold(None, |patched: Option<String>, patch| {
+        patch
+            .insert_into(patched.as_deref().unwrap_or(current))
+            .or(patched)
+    })
+}
+
+/// true if the lines at the insertion point (the start of `tail` after an anchor, else the end
+/// of `head`) are the snippet (the line endings are ignored)
+fn is_present(snippet: &str, head: &str, tail: &str, after_anchor: bool) -> bool {
+    let snippet = snippet.replace("\r\n", "\n");
+    if after_anchor {
+        let tail = tail.replace("\r\n", "\n");
+        tail.strip_prefix(&snippet)
+            .map(|rest| rest.is_empty() || rest.starts_with('\n'))
+            .unwrap_or(false)
+    } else {
+        let head = head.replace("\r\n", "\n");
+        head.strip_suffix('\n')
+            .unwrap_or(&head)
+            .strip_suffix(&snippet)
+            .map(|rest| rest.is_empty() || rest.ends_with('\n'))
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const GITIGNORE: &str = "# rust\n/target\n# ide\n.idea\n";
+
+    fn insert(after: Option<&str>, before: Option<&str>, current: &str) -> Option<String> {
+        Patch::new(".gitignore", "*.log\n", after, before)
+            .unwrap()
+            .insert_into(current)
+    }
+
+    #[test]
+    fn test_insert_at_anchors() {
+        assert_eq!(
+            insert(Some("^# rust"), None, GITIGNORE).unwrap(),
+            "# rust\n*.log\n/target\n# ide\n.idea\n"
+        );
+        assert_eq!(
+            insert(None, Some("^# ide"), GITIGNORE).unwrap(),
+            "# rust\n/target\n*.log\n# ide\n.idea\n"
+        );
+        assert_eq!(insert(None, None, "/target").unwrap(), "/target\n*.log\n");
+        // anchor not found
+        assert_eq!(
+            insert(Some("^# node"), None, GITIGNORE).unwrap(),
+            format!("{}*.log\n", GITIGNORE)
+        );
+        assert_eq!(insert(None, None, "").unwrap(), "*.log\n");
+    }
+
+    #[test]
+    fn test_insert_is_idempotent() {
+        let once = insert(Some("^# rust"), None, GITIGNORE).unwrap();
+        assert_eq!(insert(Some("^# rust"), None, &once), None);
+        let once = insert(None, Some("^# ide"), GITIGNORE).unwrap();
+        assert_eq!(insert(None, Some("^# ide"), &once), None);
+        let once = insert(None, None, GITIGNORE).unwrap();
+        assert_eq!(insert(None, None, &once), None);
+        assert_eq!(insert(None, None, "/target\r\n*.log"), None);
+    }
+
+    #[test]
+    fn test_insert_when_the_snippet_is_present_elsewhere() {
+        let current = format!("{}*.log\n", GITIGNORE);
+        assert_eq!(
+            insert(Some("^# rust"), None, &current).unwrap(),
+            "# rust\n*.log\n/target\n# ide\n.idea\n*.log\n"
+        );
+        // the snippet is a line, not a part of a line
+        assert_eq!(
+            insert(None, None, "/target\nfoo*.log\n").unwrap(),
+            "/target\nfoo*.log\n*.log\n"
+        );
+    }
+
+    #[test]
+    fn test_patch_content() {
+        let patches = vec![
+            Patch::new(".gitignore", "*.log", Some("^# rust"), None).unwrap(),
+            Patch::new(".gitignore", "*.tmp", None, None).unwrap(),
+        ];
+        let once = patch_content(&patches, GITIGNORE).unwrap();
+        assert_eq!(once, "# rust\n*.log\n/target\n# ide\n.idea\n*.tmp\n");
+        assert_eq!(patch_content(&patches, &once), None);
+    }
+
+    #[test]
+    fn test_new_should_reject_invalid_patch() {
+        assert!(Patch::new(".gitignore", "*.log", Some("^#"), Some("^#")).is_err());
+        assert!(Patch::new(".gitignore", "*.log", Some("(unclosed"), None).is_err());
+        assert!(Patch::new("../.gitignore", "*.log", None, None).is_err());
+        assert!(Patch::new("/etc/hosts", "*.log", None, None).is_err());
+    }
+}
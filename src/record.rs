@@ -0,0 +1,106 @@
+//! record of the files generated into a destination folder (stored into `.ffizer.record.yaml`),
+//! used on the next apply of the template to delete the files it no longer provides
+//! (only if their content is still the generated one).
+use crate::error::*;
+use crate::source_loc::SourceLoc;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub(crate) const RECORD_FILENAME: &str = ".ffizer.record.yaml";
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub(crate) struct Record {
+    /// by template (uri and subfolder), the md5 of the generated files by relative path
+    templates: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Record {
+    /// the record of the folder, None if the folder has no record
+    pub fn load(folder: &Path) -> Result<Option<Record>> {
+        let path = folder.join(RECORD_FILENAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content =
+            fs::read_to_string(&path).map_err(|source| Error::ReadFile { path, source })?;
+        Ok(Some(serde_yaml::from_str(&content)?))
+    }
+
+    pub fn save(&self, folder: &Path) -> Result<()> {
+        let path = folder.join(RECORD_FILENAME);
+        let content = format!(
+            "# generated by ffizer, the files generated by each template (and their md5)\n{}",
+            serde_yaml::to_string(self)?
+        );
+        fs::write(&path, content).map_err(|source| Error::WriteFile { path, source })
+    }
+
+    /// the md5 of the files generated by the template (by relative path)
+    pub fn files(&self, template: &SourceLoc) -> BTreeMap<String, String> {
+        self.templates
+            .get(&key_of_template(template))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn set_files(&mut self, template: &SourceLoc, files: BTreeMap<String, String>) {
+        self.templates.insert(key_of_template(template), files);
+    }
+}
+
+/// the revision is not part of the key (to find the files of the previous versions)
+fn key_of_template(template: &SourceLoc) -> String {
+    match &template.subfolder {
+        Some(subfolder) => format!("{}#{}", template.uri.raw, subfolder.to_string_lossy()),
+        None => template.uri.raw.clone(),
+    }
+}
+
+/// the key of a relative path (same on every platform)
+pub(crate) fn key_of_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub(crate) fn digest(path: &Path) -> Result<String> {
+    let content = fs::read(path).map_err(|source| Error::ReadFile {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(format!("{:x}", md5::compute(content)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_save_and_load() {
+        let tmp_dir = tempfile::TempDir::new().expect("create a temp dir");
+        assert_eq!(Record::load(tmp_dir.path()).unwrap(), None);
+        let template = SourceLoc {
+            uri: crate::SourceUri::from_str("https://github.com/ffizer/template_sample.git")
+                .unwrap(),
+            rev: "v1".to_owned(),
+            ..Default::default()
+        };
+        let mut record = Record::default();
+        let mut files = BTreeMap::new();
+        files.insert(key_of_path(Path::new("doc/a.md")), "0123".to_owned());
+        record.set_files(&template, files.clone());
+        record.save(tmp_dir.path()).unwrap();
+        let loaded = Record::load(tmp_dir.path()).unwrap().unwrap();
+        assert_eq!(loaded, record);
+        let upgraded = SourceLoc {
+            rev: "v2".to_owned(),
+            ..template
+        };
+        assert_eq!(loaded.files(&upgraded), files);
+    }
+}
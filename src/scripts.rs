@@ -1,23 +1,286 @@
+use crate::cfg::OnFailure;
 use crate::error::*;
-use run_script::ScriptOptions;
+use run_script::ScriptError;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Default, Clone, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(deny_unknown_fields, default)]
 pub struct Script {
     pub message: Option<String>,
     pub cmd: Option<String>,
+    /// folder where to run the command, relative to the current folder (the destination)
+    pub cwd: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    /// program used to run the command (written into a file with the extension expected by the shell:
+    /// `.ps1` for `pwsh` and `powershell`, `.bat` for `cmd`, else `.sh`), `sh` (`cmd.exe` on windows) if undefined
+    pub shell: Option<String>,
+    /// the command and its sub-processes are killed after the timeout,
+    /// so the command runs without the input of the terminal (into its own process group on unix)
+    pub timeout: Option<Duration>,
+    pub on_failure: OnFailure,
+}
+
+/// the result of a command, with its captured output
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ScriptOutput {
+    /// the exit code, None if the command was killed
+    pub code: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
 }
 
 impl Script {
-    pub(crate) fn run(&self) -> Result<()> {
-        if let Some(cmd) = &self.cmd {
-            let options = ScriptOptions::new();
-            let args = vec![];
-            run_script::run(cmd, &args, &options).map_err(|source| Error::ScriptError {
-                script: cmd.clone(),
-                source,
-            })?;
+    /// run the command and capture its output (a failure of the command is not an error, see `check`),
+    /// the output is buffered, it's available (to display) only at the end of the command
+    pub(crate) fn run(&self) -> Result<ScriptOutput> {
+        let cmd = match &self.cmd {
+            Some(cmd) => cmd,
+            None => return Ok(ScriptOutput::default()),
+        };
+        let to_error = |e| Error::ScriptError {
+            script: cmd.clone(),
+            source: ScriptError::IOError(e),
+        };
+        let shell = self
+            .shell
+            .as_deref()
+            .unwrap_or(if cfg!(windows) { "cmd.exe" } else { "sh" });
+        // like `run_script`, the command is written into a file run by the shell
+        let (extension, args) = shell_file_args(shell);
+        let mut file = tempfile::Builder::new()
+            .prefix("ffizer_script")
+            .suffix(extension)
+            .tempfile()
+            .map_err(to_error)?;
+        writeln!(file, "{}", cmd.trim()).map_err(to_error)?;
+        let file = file.into_temp_path();
+        let mut command = Command::new(shell);
+        command
+            .args(args)
+            .arg(file.as_os_str())
+            .envs(&self.env)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        if self.timeout.is_some() {
+            // a process group in background is stopped when it reads the terminal
+            command.stdin(Stdio::null());
+            #[cfg(unix)]
+            std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        }
+        let mut child = command.spawn().map_err(to_error)?;
+        let stdout = capture(child.stdout.take());
+        let stderr = capture(child.stderr.take());
+        let status = wait(&mut child, self.timeout).map_err(to_error)?;
+        // when killed, the output is read without waiting the end of the sub-processes
+        if status.is_some() {
+            for reader in [stdout.1, stderr.1].into_iter().flatten() {
+                let _ = reader.join();
+            }
+        }
+        let text = |buffer: &Arc<Mutex<Vec<u8>>>| {
+            let content = buffer.lock().map(|v| v.clone()).unwrap_or_default();
+            String::from_utf8_lossy(&content).into_owned()
+        };
+        Ok(ScriptOutput {
+            code: status.and_then(|s| s.code()),
+            timed_out: status.is_none(),
+            stdout: text(&stdout.0),
+            stderr: text(&stderr.0),
+        })
+    }
+
+    /// the error if the command failed (non-zero exit code) or timed out
+    pub(crate) fn check(&self, output: &ScriptOutput) -> Result<()> {
+        let script = self.cmd.clone().unwrap_or_default();
+        match (output.timed_out, output.code, self.timeout) {
+            (true, _, Some(timeout)) => Err(Error::ScriptTimeout { script, timeout }),
+            (_, Some(0), _) => Ok(()),
+            (_, code, _) => Err(Error::ScriptFailed {
+                script,
+                code: code.unwrap_or(-1),
+            }),
+        }
+    }
+}
+
+/// the extension of the file of the command and the arguments (before the file) expected by the shell
+fn shell_file_args(shell: &str) -> (&'static str, &'static [&'static str]) {
+    let name = Path::new(shell)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(shell)
+        .to_ascii_lowercase();
+    match name.as_str() {
+        "cmd" => (".bat", &["/C"]),
+        "pwsh" | "powershell" => (".ps1", &["-File"]),
+        _ => (".sh", &[]),
+    }
+}
+
+type Captured = (Arc<Mutex<Vec<u8>>>, Option<thread::JoinHandle<()>>);
+
+/// read the stream into a buffer (from an other thread, to not block the process)
+fn capture<R: Read + Send + 'static>(stream: Option<R>) -> Captured {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let reader = stream.map(|mut stream| {
+        let buffer = buffer.clone();
+        thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            while let Ok(n) = stream.read(&mut chunk) {
+                match buffer.lock() {
+                    Ok(mut b) if n > 0 => b.extend_from_slice(&chunk[..n]),
+                    _ => break,
+                }
+            }
+        })
+    });
+    (buffer, reader)
+}
+
+/// the status of the process, None if it was killed (with its sub-processes) after the timeout
+fn wait(child: &mut Child, timeout: Option<Duration>) -> std::io::Result<Option<ExitStatus>> {
+    let timeout = match timeout {
+        Some(v) => v,
+        None => return child.wait().map(Some),
+    };
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout {
+            kill_tree(child)?;
+            child.wait()?;
+            return Ok(None);
         }
-        Ok(())
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// kill the process group of the child (see `process_group` into `Script::run`)
+#[cfg(all(unix, not(target_os = "redox")))]
+fn kill_tree(child: &mut Child) -> std::io::Result<()> {
+    // SAFETY: `kill` has no precondition, the child is the leader of its process group
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } != 0 {
+        return child.kill();
+    }
+    Ok(())
+}
+
+/// kill the child and its sub-processes
+#[cfg(windows)]
+fn kill_tree(child: &mut Child) -> std::io::Result<()> {
+    let status = Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &child.id().to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return child.kill();
+    }
+    Ok(())
+}
+
+#[cfg(not(any(all(unix, not(target_os = "redox")), windows)))]
+fn kill_tree(child: &mut Child) -> std::io::Result<()> {
+    child.kill()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_with_env_and_capture() {
+        let mut env = BTreeMap::new();
+        env.insert("WHO".to_owned(), "world".to_owned());
+        let script = Script {
+            cmd: Some("echo hello $WHO\necho oops >&2".to_owned()),
+            env,
+            ..Default::default()
+        };
+        let output = script.run().unwrap();
+        assert_eq!(output.stdout, "hello world\n");
+        assert_eq!(output.stderr, "oops\n");
+        assert!(script.check(&output).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_with_shell() {
+        let script = Script {
+            cmd: Some("echo ${BASH_VERSION:+bash}".to_owned()),
+            shell: Some("bash".to_owned()),
+            ..Default::default()
+        };
+        let output = script.run().unwrap();
+        assert_eq!(output.stdout, "bash\n");
+        assert!(script.check(&output).is_ok());
+    }
+
+    #[test]
+    fn test_shell_file_args() {
+        assert_eq!(shell_file_args("sh"), (".sh", &[][..]));
+        assert_eq!(shell_file_args("/bin/bash"), (".sh", &[][..]));
+        assert_eq!(shell_file_args("cmd.exe"), (".bat", &["/C"][..]));
+        assert_eq!(shell_file_args("pwsh"), (".ps1", &["-File"][..]));
+        assert_eq!(shell_file_args("PowerShell.exe"), (".ps1", &["-File"][..]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_failure_and_timeout() {
+        let script = Script {
+            cmd: Some("exit 3".to_owned()),
+            ..Default::default()
+        };
+        let output = script.run().unwrap();
+        assert_eq!(output.code, Some(3));
+        assert!(matches!(
+            script.check(&output),
+            Err(Error::ScriptFailed { code: 3, .. })
+        ));
+
+        let script = Script {
+            cmd: Some("sleep 5".to_owned()),
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let output = script.run().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert!(output.timed_out);
+        assert!(matches!(
+            script.check(&output),
+            Err(Error::ScriptTimeout { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout_kills_the_sub_processes() {
+        let tmp_dir = tempfile::TempDir::new().expect("create a temp dir");
+        let script = Script {
+            cmd: Some("(sleep 1; touch marker) &\nwait".to_owned()),
+            cwd: Some(tmp_dir.path().to_path_buf()),
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let output = script.run().unwrap();
+        assert!(output.timed_out);
+        thread::sleep(Duration::from_millis(1500));
+        assert!(!tmp_dir.path().join("marker").exists());
     }
 }
@@ -1,4 +1,5 @@
-use crate::files;
+use crate::cfg::{Eol, FileStrategy};
+use crate::engine::EngineKind;
 use crate::ChildPath;
 use std::cmp::{Ord, Ordering};
 use std::path::PathBuf;
@@ -6,22 +7,38 @@ use std::path::PathBuf;
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum SourceFileMetadata {
     Dir,
-    // TODO Symlink { target: String },
+    /// a folder declared into the configuration (`directories`), with its permissions
+    DeclaredDir {
+        mode: Option<u32>,
+    },
+    /// a symlink (preserved), its target is rendered
+    Symlink {
+        target: String,
+    },
     RawFile,
-    RenderableFile { extension: &'static str },
+    RenderableFile {
+        extension: &'static str,
+    },
 }
 
 impl SourceFileMetadata {
     fn kind_idx(&self) -> usize {
         match self {
-            // Self::Symlink { .. } => 0,
-            Self::Dir => 1,
+            Self::Symlink { .. } => 0,
+            Self::Dir | Self::DeclaredDir { .. } => 1,
             Self::RenderableFile { .. } => 2,
             Self::RawFile => 3,
         }
     }
 }
 
+impl SourceFileMetadata {
+    /// true for the kinds with a content (raw or renderable file)
+    pub fn is_file(&self) -> bool {
+        matches!(self, Self::RawFile | Self::RenderableFile { .. })
+    }
+}
+
 impl Ord for SourceFileMetadata {
     fn cmp(&self, other: &Self) -> Ordering {
         self.kind_idx().cmp(&other.kind_idx())
@@ -39,6 +56,31 @@ pub struct SourceFile {
     pub childpath: ChildPath,
     pub layer_order: usize,
     pub metadata: SourceFileMetadata,
+    /// how the file is combined with the same destination from the next layers
+    pub strategy: FileStrategy,
+    /// the path to use (instead of the path into the template) to compute the destination
+    pub rename_to: Option<PathBuf>,
+    /// generate the file for each item of a list (see `files.for_each`)
+    pub for_each: Option<FileLoop>,
+    /// line endings of the generated file (if defined by a rule of `files`)
+    pub eol: Option<Eol>,
+    /// line endings of the template, used if neither `eol` nor the `.gitattributes` of the destination define them
+    pub template_eol: Option<Eol>,
+    /// command to format the generated file (see `formatters`)
+    pub formatter: Option<String>,
+    /// engine to render the name (and the content) of the file
+    pub engine: EngineKind,
+}
+
+/// the generation of a file (or folder) for each item of a list variable
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileLoop {
+    /// name of the variable with the list of items
+    pub variable: String,
+    /// name of the variable with the current item (during the rendering)
+    pub item_name: String,
+    /// the current item (defined by the plan)
+    pub item: Option<serde_yaml::Value>,
 }
 
 impl Ord for SourceFile {
@@ -58,12 +100,6 @@ impl PartialOrd for SourceFile {
     }
 }
 
-impl SourceFile {
-    pub fn childpath(&self) -> &ChildPath {
-        &self.childpath
-    }
-}
-
 // // TODO add test
 // // TODO add priority for generated file name / folder name
 // // TODO document priority (via test ?)
@@ -89,44 +125,140 @@ impl SourceFile {
 // }
 
 impl From<(ChildPath, usize)> for SourceFile {
-    //TODO manage symlink
     fn from((childpath, layer_order): (ChildPath, usize)) -> Self {
+        SourceFile::new(childpath, layer_order, false)
+    }
+}
+
+impl SourceFile {
+    pub fn childpath(&self) -> &ChildPath {
+        &self.childpath
+    }
+
+    /// force the file to be rendered or copied as is (see `render` and `raw` of `.ffizer.yaml`)
+    pub fn set_renderable(&mut self, renderable: bool) {
+        match (&self.metadata, renderable) {
+            (SourceFileMetadata::RawFile, true) => {
+                self.metadata = SourceFileMetadata::RenderableFile {
+                    extension: self.engine.file_extension(),
+                };
+                self.strategy = FileStrategy::RenderChain;
+            }
+            (SourceFileMetadata::RenderableFile { .. }, false) => {
+                self.metadata = SourceFileMetadata::RawFile;
+                self.strategy = FileStrategy::Override;
+            }
+            _ => (),
+        }
+    }
+
+    /// a symlink is kept as is, except if `follow_links` (then it's the targeted file or folder)
+    pub fn new(childpath: ChildPath, layer_order: usize, follow_links: bool) -> Self {
         let path = PathBuf::from(&childpath);
-        //let metadata = std::fs::symlink_metadata(path)?;
-        if path.is_dir() {
+        let symlink_target = if follow_links {
+            None
+        } else {
+            std::fs::symlink_metadata(&path)
+                .ok()
+                .filter(|m| m.file_type().is_symlink())
+                .and_then(|_| std::fs::read_link(&path).ok())
+        };
+        if let Some(target) = symlink_target {
+            SourceFile {
+                childpath,
+                layer_order,
+                metadata: SourceFileMetadata::Symlink {
+                    target: target.to_string_lossy().to_string(),
+                },
+                strategy: FileStrategy::Override,
+                rename_to: None,
+                for_each: None,
+                eol: None,
+                template_eol: None,
+                formatter: None,
+                engine: EngineKind::default(),
+            }
+        } else if path.is_dir() {
             SourceFile {
                 childpath,
                 layer_order,
                 metadata: SourceFileMetadata::Dir,
+                strategy: FileStrategy::Override,
+                rename_to: None,
+                for_each: None,
+                eol: None,
+                template_eol: None,
+                formatter: None,
+                engine: EngineKind::default(),
             }
-        } else if files::is_ffizer_handlebars(&path) {
+        } else if let Some(engine) = EngineKind::from_path(&path) {
             SourceFile {
                 childpath,
                 layer_order,
                 metadata: SourceFileMetadata::RenderableFile {
-                    extension: files::FILEEXT_HANDLEBARS,
+                    extension: engine.file_extension(),
                 },
+                strategy: FileStrategy::RenderChain,
+                rename_to: None,
+                for_each: None,
+                eol: None,
+                template_eol: None,
+                formatter: None,
+                engine,
             }
         } else {
             SourceFile {
                 childpath,
                 layer_order,
                 metadata: SourceFileMetadata::RawFile,
+                strategy: FileStrategy::Override,
+                rename_to: None,
+                for_each: None,
+                eol: None,
+                template_eol: None,
+                formatter: None,
+                engine: EngineKind::default(),
             }
         }
     }
 }
 
+/// select the sources to apply for a same destination: sorted by priority (the layer nearest to
+/// the root template first), until a source that erases the content of the next ones
+/// (a folder, a symlink, a file with the strategy `override`).
+/// A file with the strategy `keep_first` is removed if a next source provides a content.
 pub(crate) fn optimize_sourcefiles(sources: &mut Vec<SourceFile>) {
     sources.sort();
-    // take until not Renderable
-    // Because apply Dir, Symlink, RawFile erase previous change
-    if let Some(pos) = sources
-        .iter()
-        .position(|x| !matches!(x.metadata, SourceFileMetadata::RenderableFile { .. }))
-    {
-        sources.truncate(pos + 1);
+    let mut selected = Vec::with_capacity(sources.len());
+    for (i, source) in sources.iter().enumerate() {
+        if !source.metadata.is_file() {
+            // a folder or a symlink can not be combined with files
+            if selected.is_empty() {
+                selected.push(source.clone());
+            }
+            break;
+        }
+        match source.strategy {
+            FileStrategy::Override => {
+                selected.push(source.clone());
+                break;
+            }
+            FileStrategy::KeepFirst => {
+                let has_next_content = sources
+                    .get(i + 1)
+                    .map(|next| next.metadata.is_file())
+                    .unwrap_or(false);
+                if !has_next_content {
+                    selected.push(source.clone());
+                }
+            }
+            FileStrategy::Append
+            | FileStrategy::Prepend
+            | FileStrategy::RenderChain
+            | FileStrategy::DeepMerge => selected.push(source.clone()),
+        }
     }
+    *sources = selected;
 }
 
 #[cfg(test)]
@@ -155,4 +287,38 @@ mod tests {
         optimize_sourcefiles(&mut input);
         assert_that!(&input).is_equal_to(&expected);
     }
+
+    #[test]
+    fn test_optimize_sourcefiles_with_strategies() {
+        let mk_source = |layer_order: usize, strategy: FileStrategy| {
+            let mut source = SourceFile::from((
+                ChildPath::new(format!("./tests/src{}", layer_order), "file.txt"),
+                layer_order,
+            ));
+            source.strategy = strategy;
+            source
+        };
+        let dir = SourceFile::from((ChildPath::new("./tests", "data"), 3));
+
+        let mut input = vec![
+            dir.clone(),
+            mk_source(2, FileStrategy::Override),
+            mk_source(1, FileStrategy::KeepFirst),
+            mk_source(0, FileStrategy::Prepend),
+        ];
+        optimize_sourcefiles(&mut input);
+        assert_that!(&input).is_equal_to(vec![
+            mk_source(0, FileStrategy::Prepend),
+            mk_source(2, FileStrategy::Override),
+        ]);
+
+        // keep_first is used when no next layer provides a content
+        let mut input = vec![dir.clone(), mk_source(1, FileStrategy::KeepFirst)];
+        optimize_sourcefiles(&mut input);
+        assert_that!(&input).is_equal_to(vec![mk_source(1, FileStrategy::KeepFirst)]);
+
+        let mut input = vec![dir.clone(), mk_source(1, FileStrategy::Override)];
+        optimize_sourcefiles(&mut input);
+        assert_that!(&input).is_equal_to(vec![mk_source(1, FileStrategy::Override)]);
+    }
 }
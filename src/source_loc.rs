@@ -1,11 +1,83 @@
+use crate::cli_opt::GitOpts;
 use crate::error::*;
+use crate::files;
 use crate::git;
 use crate::source_uri::SourceUri;
 use clap::Args;
+use fs2::FileExt;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
 use std::fmt;
 use std::fs;
-use std::path::PathBuf;
-use tracing::warn;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tracing::{debug, warn};
+
+lazy_static! {
+    /// the snapshot of every cache folder used by the process (see `SourceLoc::download`),
+    /// several SourceLoc can share the same cache folder (eg: different subfolders)
+    static ref CACHE_SNAPSHOTS: Mutex<HashMap<PathBuf, Arc<Mutex<Option<TempDir>>>>> =
+        Mutex::new(HashMap::new());
+}
+
+fn find_cache_snapshot(path: &Path) -> Arc<Mutex<Option<TempDir>>> {
+    CACHE_SNAPSHOTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(path.to_path_buf())
+        .or_default()
+        .clone()
+}
+
+/// a file lock (`<cache folder>.lock`) shared between ffizer processes:
+/// exclusive to retrieve (write) the cache folder, shared to read it.
+/// The lock is released when dropped (the file is closed).
+/// It is held only to retrieve and to copy the cache folder (see `SourceLoc::download`),
+/// never while waiting for an other lock, so processes can not deadlock.
+#[derive(Debug)]
+struct CacheLock {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl CacheLock {
+    fn open(cache_folder: &Path) -> Result<CacheLock> {
+        let path = files::add_suffix(cache_folder, ".lock")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|source| Error::CreateFolder {
+                path: parent.to_path_buf(),
+                source,
+            })?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|source| Error::LockFile {
+                path: path.clone(),
+                source,
+            })?;
+        Ok(CacheLock { path, file })
+    }
+
+    fn lock_exclusive(&self) -> Result<()> {
+        debug!(path = ?self.path, "wait for exclusive lock");
+        FileExt::lock_exclusive(&self.file).map_err(|source| Error::LockFile {
+            path: self.path.clone(),
+            source,
+        })
+    }
+
+    fn lock_shared(&self) -> Result<()> {
+        debug!(path = ?self.path, "wait for shared lock");
+        FileExt::lock_shared(&self.file).map_err(|source| Error::LockFile {
+            path: self.path.clone(),
+            source,
+        })
+    }
+}
 
 #[derive(Args, Debug, Default, Clone, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(deny_unknown_fields, default)]
@@ -21,6 +93,10 @@ pub struct SourceLoc {
     /// path of the folder under the source uri to use for template
     #[arg(long = "source-subfolder", value_name = "FOLDER")]
     pub subfolder: Option<PathBuf>,
+
+    /// initialize and update the git submodules of the template (recursively)
+    #[arg(long = "source-submodules")]
+    pub submodules: bool,
 }
 
 impl SourceLoc {
@@ -42,7 +118,15 @@ impl SourceLoc {
                     path: self.uri.path.clone(),
                     source,
                 })?,
-            Some(_) => self.remote_as_local()?,
+            Some(_) => {
+                let remote_path = self.remote_as_local()?;
+                let snapshot = find_cache_snapshot(&remote_path);
+                let snapshot = snapshot.lock().unwrap_or_else(|e| e.into_inner());
+                match snapshot.as_ref() {
+                    Some(snapshot) => snapshot.path().to_path_buf(),
+                    None => remote_path,
+                }
+            }
         };
         if let Some(f) = &self.subfolder {
             path = path.join(f.clone());
@@ -58,23 +142,18 @@ impl SourceLoc {
             .join(&self.rev);
         Ok(cache_uri)
     }
-    pub fn download(&self, offline: bool) -> Result<PathBuf> {
-        if !offline && self.uri.host.is_some() {
+
+    /// retrieve the template into the cache (if remote and not offline),
+    /// and copy it into a snapshot used until the end of the process,
+    /// so the cache is not locked while the template is used (eg: during the questions to the user)
+    pub fn download(&self, offline: bool, git_opts: &GitOpts) -> Result<PathBuf> {
+        if self.uri.host.is_some() {
             let remote_path = self.remote_as_local()?;
-            if let Err(v) = git::retrieve(&remote_path, &self.uri.raw, &self.rev) {
-                warn!(
-                    src = ?self,
-                    path = ?remote_path,
-                    error = ?v,
-                    "failed to download"
-                );
-                if remote_path.exists() {
-                    fs::remove_dir_all(&remote_path).map_err(|source| Error::RemoveFolder {
-                        path: remote_path,
-                        source,
-                    })?;
-                }
-                return Err(v);
+            let snapshot = find_cache_snapshot(&remote_path);
+            let mut snapshot = snapshot.lock().unwrap_or_else(|e| e.into_inner());
+            // the cache folder is retrieved once by process
+            if snapshot.is_none() {
+                *snapshot = Some(self.retrieve_into_snapshot(&remote_path, offline, git_opts)?);
             }
         }
         let path = self.as_local_path()?;
@@ -88,6 +167,75 @@ impl SourceLoc {
             Ok(path)
         }
     }
+
+    fn retrieve_into_snapshot(
+        &self,
+        remote_path: &Path,
+        offline: bool,
+        git_opts: &GitOpts,
+    ) -> Result<TempDir> {
+        let cache_lock = CacheLock::open(remote_path)?;
+        if offline {
+            cache_lock.lock_shared()?;
+        } else {
+            cache_lock.lock_exclusive()?;
+            // a failed retrieve keeps the previous cached template (a failed clone is not cached)
+            if let Err(v) = git::retrieve(
+                remote_path,
+                &self.uri.raw,
+                &self.rev,
+                self.submodules,
+                git_opts,
+            ) {
+                if git_opts.prefer_offline && remote_path.exists() {
+                    warn!(
+                        src = ?self,
+                        path = ?remote_path,
+                        error = ?v,
+                        "failed to download, use the cached template"
+                    );
+                } else {
+                    warn!(
+                        src = ?self,
+                        path = ?remote_path,
+                        error = ?v,
+                        "failed to download"
+                    );
+                    return Err(v);
+                }
+            }
+        }
+        if !remote_path.exists() {
+            return Err(crate::Error::LocalPathNotFound {
+                path: remote_path.to_path_buf(),
+                uri: self.uri.raw.clone(),
+                subfolder: self.subfolder.clone(),
+            });
+        }
+        let snapshot = tempfile::Builder::new()
+            .prefix("ffizer-template-")
+            .tempdir()?;
+        debug!(path = ?remote_path, snapshot = ?snapshot.path(), "copy cached template");
+        files::copy_folder(remote_path, snapshot.path(), &[".git"])?;
+        Ok(snapshot)
+    }
+
+    /// remove the snapshots of the cached templates (see `download`), to call at the end of the process
+    pub fn remove_snapshots() {
+        let snapshots =
+            std::mem::take(&mut *CACHE_SNAPSHOTS.lock().unwrap_or_else(|e| e.into_inner()));
+        for (path, snapshot) in snapshots {
+            if let Some(snapshot) = snapshot.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                if let Err(err) = snapshot.close() {
+                    warn!(
+                        ?path,
+                        ?err,
+                        "failed to remove the snapshot of the cached template"
+                    );
+                }
+            }
+        }
+    }
 }
 
 impl fmt::Display for SourceLoc {
@@ -122,3 +270,32 @@ impl fmt::Display for SourceLoc {
 //         Ok(())
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs2::FileExt;
+    use tempfile::tempdir;
+
+    #[test]
+    fn cache_lock_should_block_writer_while_reading() {
+        let tmp_dir = tempdir().unwrap();
+        let cache_folder = tmp_dir.path().join("host").join("repo").join("master");
+        let writer = CacheLock::open(&cache_folder).unwrap();
+        let reader = CacheLock::open(&cache_folder).unwrap();
+        assert!(files::add_suffix(&cache_folder, ".lock").unwrap().exists());
+
+        writer.lock_exclusive().unwrap();
+        assert!(FileExt::try_lock_shared(&reader.file).is_err());
+
+        drop(writer);
+        assert!(FileExt::try_lock_shared(&reader.file).is_ok());
+        FileExt::unlock(&reader.file).unwrap();
+
+        let other_writer = CacheLock::open(&cache_folder).unwrap();
+        reader.lock_shared().unwrap();
+        assert!(FileExt::try_lock_exclusive(&other_writer.file).is_err());
+        drop(reader);
+        assert!(FileExt::try_lock_exclusive(&other_writer.file).is_ok());
+    }
+}
@@ -0,0 +1,152 @@
+//! helpers and partials provided by the templates,
+//! registered with the helpers of `handlebars_misc_helpers`.
+use crate::engine::Engines;
+use crate::error::*;
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError,
+};
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// a helper defined by a handlebars template (see `helpers` of `.ffizer.yaml`),
+/// the template is rendered with the root context (the variables), not the current item of a block
+/// (eg: `{{#each}}`), so the item should be passed as parameter (eg: `{{ my_helper this }}`)
+#[derive(Debug, Clone)]
+pub(crate) struct TemplateHelper {
+    pub template: String,
+}
+
+impl HelperDef for TemplateHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let mut data = ctx.data().clone();
+        if let serde_json::Value::Object(m) = &mut data {
+            let params = h.params().iter().map(|p| p.value().clone()).collect();
+            m.insert("params".to_owned(), serde_json::Value::Array(params));
+            for (k, v) in h.hash() {
+                m.insert((*k).to_owned(), v.value().clone());
+            }
+        }
+        let rendered = r
+            .render_template(&self.template, &data)
+            .map_err(|e| RenderError::new(format!("helper '{}': {}", h.name(), e.desc)))?;
+        out.write(&rendered)?;
+        Ok(())
+    }
+}
+
+/// register the files of `folder` as partials, named by their path relative to `folder`
+/// without extension (eg: `rust/header.hbs` as `rust/header`), the files with the extension `.j2`
+/// are templates to include with the jinja engine (eg: `{% include "rust/header" %}`)
+pub(crate) fn register_partials(engines: &mut Engines, folder: &Path) -> Result<()> {
+    for entry in WalkDir::new(folder).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(folder)
+            .expect("scanned path to be under folder");
+        let mut name = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let is_jinja = name.ends_with(".j2");
+        let file_name_start = name.rfind('/').map(|i| i + 1).unwrap_or(0);
+        if let Some(dot) = name[file_name_start..].find('.').filter(|i| *i > 0) {
+            name.truncate(file_name_start + dot);
+        }
+        let content = fs::read_to_string(entry.path()).map_err(|source| Error::ReadFile {
+            path: entry.path().to_path_buf(),
+            source,
+        })?;
+        let when = format!("register partial '{}'", name);
+        let template = entry.path().to_string_lossy().to_string();
+        if is_jinja {
+            engines
+                .jinja
+                .add_template_owned(name, content)
+                .map_err(|source| Error::Jinja {
+                    when,
+                    template,
+                    source,
+                })?;
+        } else {
+            engines
+                .handlebars
+                .register_template_string(&name, content)
+                .map_err(RenderError::from)
+                .map_err(|source| Error::Handlebars {
+                    when,
+                    template,
+                    source,
+                })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use handlebars_misc_helpers::new_hbs;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_template_helper_with_params() {
+        let mut handlebars = new_hbs();
+        handlebars.register_helper(
+            "greet",
+            Box::new(TemplateHelper {
+                template: "{{ params.[0] }} {{ name }}{{ suffix }}".to_owned(),
+            }),
+        );
+        let data = serde_json::json!({"name": "world"});
+        let actual = handlebars
+            .render_template(r#"{{ greet "hello" suffix="!" }}"#, &data)
+            .unwrap();
+        assert_eq!(actual, "hello world!");
+        let data = serde_json::json!({"name": "world", "items": ["hi", "bye"]});
+        let actual = handlebars
+            .render_template(
+                r#"{{#each items}}{{ greet this suffix="," }}{{/each}}"#,
+                &data,
+            )
+            .unwrap();
+        assert_eq!(actual, "hi world,bye world,");
+    }
+
+    #[test]
+    fn test_register_partials_by_relative_path() {
+        let tmp_dir = tempfile::TempDir::new().expect("create a temp dir");
+        fs::create_dir_all(tmp_dir.path().join("rust")).unwrap();
+        fs::write(
+            tmp_dir.path().join("rust").join("header.hbs"),
+            "// {{ name }}",
+        )
+        .unwrap();
+        fs::write(tmp_dir.path().join("footer.md.hbs"), "bye").unwrap();
+        fs::write(tmp_dir.path().join("footer.md.j2"), "{{ name }}!").unwrap();
+        let mut engines = Engines::default();
+        register_partials(&mut engines, tmp_dir.path()).unwrap();
+        let data = serde_json::json!({"name": "world"});
+        let actual = engines
+            .handlebars
+            .render_template("{{> rust/header }} {{> footer }}", &data)
+            .unwrap();
+        assert_eq!(actual, "// world bye");
+        let actual = engines
+            .jinja
+            .render_str(r#"{% include "footer" %}"#, &data)
+            .unwrap();
+        assert_eq!(actual, "world!");
+    }
+}
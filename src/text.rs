@@ -0,0 +1,114 @@
+//! detection of the binary files, and of the encoding of the text files
+//! (utf-8, utf-16 with a BOM, latin-1 as fallback) to render them without corruption.
+use crate::error::*;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// size of the head of a content used to detect a binary
+const SNIFF_LEN: usize = 8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+/// a content is binary if it has a NUL byte in its head (except utf-16 with a BOM)
+pub(crate) fn is_binary(content: &[u8]) -> bool {
+    if content.starts_with(&[0xFF, 0xFE]) || content.starts_with(&[0xFE, 0xFF]) {
+        return false;
+    }
+    content.iter().take(SNIFF_LEN).any(|b| *b == 0)
+}
+
+pub(crate) fn is_binary_file(path: &Path) -> Result<bool> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    fs::File::open(path)
+        .and_then(|f| f.take(SNIFF_LEN as u64).read_to_end(&mut head))
+        .map_err(|source| Error::ReadFile {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(is_binary(&head))
+}
+
+/// the text of the content (without the BOM of utf-16) and its encoding
+pub(crate) fn decode(content: &[u8]) -> (String, Encoding) {
+    let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+        let units = bytes
+            .chunks_exact(2)
+            .map(|c| from([c[0], c[1]]))
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&units)
+    };
+    if let Some(bytes) = content.strip_prefix(&[0xFF, 0xFE]) {
+        (utf16(bytes, u16::from_le_bytes), Encoding::Utf16Le)
+    } else if let Some(bytes) = content.strip_prefix(&[0xFE, 0xFF]) {
+        (utf16(bytes, u16::from_be_bytes), Encoding::Utf16Be)
+    } else {
+        match std::str::from_utf8(content) {
+            Ok(s) => (s.to_owned(), Encoding::Utf8),
+            Err(_) => (
+                content.iter().map(|b| char::from(*b)).collect(),
+                Encoding::Latin1,
+            ),
+        }
+    }
+}
+
+/// the content of the text (with the BOM for utf-16),
+/// the characters not supported by latin-1 are replaced by `?`
+pub(crate) fn encode(text: &str, encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::Utf8 => text.as_bytes().to_vec(),
+        Encoding::Utf16Le => [0xFF, 0xFE]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect(),
+        Encoding::Utf16Be => [0xFE, 0xFF]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+            .collect(),
+        Encoding::Latin1 => text
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_is_binary() {
+        assert!(!is_binary(b"hello\nworld"));
+        assert!(is_binary(b"\x1f\x8b\x08\x00\x00\x00"));
+        assert!(!is_binary(&encode("hello", Encoding::Utf16Le)));
+    }
+
+    #[test]
+    fn test_decode_encode_roundtrip() {
+        for (content, encoding) in [
+            ("héllo {{ name }}".as_bytes().to_vec(), Encoding::Utf8),
+            (b"h\xe9llo {{ name }}".to_vec(), Encoding::Latin1),
+            (
+                encode("héllo {{ name }}", Encoding::Utf16Le),
+                Encoding::Utf16Le,
+            ),
+            (
+                encode("héllo {{ name }}", Encoding::Utf16Be),
+                Encoding::Utf16Be,
+            ),
+        ] {
+            let (text, actual_encoding) = decode(&content);
+            assert_eq!(text, "héllo {{ name }}");
+            assert_eq!(actual_encoding, encoding);
+            assert_eq!(encode(&text, encoding), content);
+        }
+    }
+}
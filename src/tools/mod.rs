@@ -1,9 +1,9 @@
 pub mod dir_diff_list;
 
 use crate::cli_opt::{ApplyOpts, CliOpts, Command, TestSamplesOpts};
+use crate::error::*;
 use crate::path_pattern::PathPattern;
-use crate::{error::*, SourceLoc};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use dir_diff_list::Difference;
 use dir_diff_list::EntryDiff;
 use std::fs;
@@ -12,21 +12,21 @@ use tempfile::{tempdir, TempDir};
 use tracing::info;
 
 pub fn test_samples(cfg: &TestSamplesOpts) -> Result<()> {
-    let template_base_path = &cfg.src.download(cfg.offline)?;
-    if !check_samples(template_base_path, &cfg.src)? {
+    let template_base_path = &cfg.src.download(cfg.offline, &cfg.git)?;
+    if !check_samples(template_base_path, cfg)? {
         Err(crate::Error::TestSamplesFailed {})
     } else {
         Ok(())
     }
 }
 
-fn check_samples<A: AsRef<Path>>(template_path: A, template_loc: &SourceLoc) -> Result<bool> {
+fn check_samples<A: AsRef<Path>>(template_path: A, cfg: &TestSamplesOpts) -> Result<bool> {
     let mut is_success = true;
     let tmp_dir = tempdir()?;
     let samples_folder = template_path
         .as_ref()
         .join(crate::cfg::TEMPLATE_SAMPLES_DIRNAME);
-    let samples = Sample::find_from_folder(template_loc, &samples_folder, &tmp_dir)?;
+    let samples = Sample::find_from_folder(cfg, &samples_folder, &tmp_dir)?;
     info!(nb_samples_detected = samples.len(), ?samples_folder);
     for sample in samples {
         info!(sample = ?sample.name, args = ?sample.args, "checking...");
@@ -116,7 +116,7 @@ struct Sample {
 impl Sample {
     // scan folder to find sample to test (xxx.args, xxx.expected, xxx.existing)
     fn find_from_folder<B: AsRef<Path>>(
-        template_loc: &SourceLoc,
+        cfg: &TestSamplesOpts,
         samples_folder: B,
         tmp_dir: &TempDir,
     ) -> Result<Vec<Sample>> {
@@ -141,7 +141,7 @@ impl Sample {
                 let args_file = path.with_extension("cfg.yaml");
                 let destination = tmp_dir.path().join(&name).to_path_buf();
                 let sample_cfg = SampleCfg::from_file(args_file)?;
-                let args = sample_cfg.make_args(template_loc, destination)?;
+                let args = sample_cfg.make_args(cfg, destination)?;
                 let ignores = sample_cfg.make_ignores()?;
                 out.push(Sample {
                     name,
@@ -193,11 +193,32 @@ impl SampleCfg {
 
     fn make_args<B: AsRef<Path>>(
         &self,
-        template_loc: &SourceLoc,
+        cfg: &TestSamplesOpts,
         destination: B,
     ) -> Result<ApplyOpts> {
+        let template_loc = &cfg.src;
+        let git_opts = &cfg.git;
         let cfg_args = self.apply_args.clone().unwrap_or_default();
         let mut args_line = cfg_args.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+        // the options of `test-samples` are used by the samples that don't define them
+        let has_arg = |name: &str| {
+            cfg_args
+                .iter()
+                .any(|a| a == name || a.starts_with(&format!("{}=", name)))
+        };
+        let import_conflict = value_name(&cfg.import_conflict);
+        if !has_arg("--import-conflict") {
+            args_line.push("--import-conflict");
+            args_line.push(&import_conflict);
+        }
+        let symlinks = value_name(&cfg.symlinks);
+        if !has_arg("--symlinks") {
+            args_line.push("--symlinks");
+            args_line.push(&symlinks);
+        }
+        if cfg.record && !has_arg("--record") {
+            args_line.push("--record");
+        }
         args_line.push("--confirm");
         args_line.push("never");
         args_line.push("--no-interaction");
@@ -217,6 +238,17 @@ impl SampleCfg {
             args_line.push("--source-subfolder");
             args_line.push(subfolder);
         }
+        if template_loc.submodules {
+            args_line.push("--source-submodules");
+        }
+        let buff = git_opts.ssh_key.as_ref().map(|v| v.to_string_lossy());
+        if let Some(ssh_key) = buff.as_ref() {
+            args_line.push("--ssh-key");
+            args_line.push(ssh_key);
+        }
+        if git_opts.prefer_offline {
+            args_line.push("--prefer-offline");
+        }
         //HACK from_iter_safe expect first entry to be the binary name,
         //  unless clap::AppSettings::NoBinaryName has been used
         //  (but I don't know how to use it in this case, patch is welcomed)
@@ -234,6 +266,13 @@ impl SampleCfg {
     }
 }
 
+/// the name of the value of an option (as used on the command line)
+fn value_name<T: ValueEnum>(v: &T) -> String {
+    v.to_possible_value()
+        .map(|p| p.get_name().to_owned())
+        .unwrap_or_default()
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct SampleRun {
     diffs: Vec<EntryDiff>,
@@ -314,3 +353,40 @@ pub fn copy<U: AsRef<Path>, V: AsRef<Path>>(from: U, to: V) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli_opt::{ImportConflictPolicy, SymlinkPolicy};
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    #[test]
+    fn make_args_should_use_options_not_defined_by_the_sample() {
+        let cfg = TestSamplesOpts {
+            src: crate::SourceLoc {
+                uri: crate::SourceUri::from_str("tests/data/template_1").unwrap(),
+                rev: "master".to_owned(),
+                ..Default::default()
+            },
+            import_conflict: ImportConflictPolicy::PreferNearest,
+            symlinks: SymlinkPolicy::Follow,
+            record: true,
+            ..Default::default()
+        };
+        let args = SampleCfg::default().make_args(&cfg, "dst").unwrap();
+        assert_eq!(args.import_conflict, ImportConflictPolicy::PreferNearest);
+        assert_eq!(args.symlinks, SymlinkPolicy::Follow);
+        assert!(args.record);
+
+        let sample_cfg = SampleCfg {
+            apply_args: Some(vec![
+                "--import-conflict".to_owned(),
+                "prefer-highest".to_owned(),
+            ]),
+            ..Default::default()
+        };
+        let args = sample_cfg.make_args(&cfg, "dst").unwrap();
+        assert_eq!(args.import_conflict, ImportConflictPolicy::PreferHighest);
+    }
+}
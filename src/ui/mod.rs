@@ -1,9 +1,12 @@
 mod tree;
 
+use crate::cfg::LayerVariableCfgs;
 use crate::cfg::TransformsValues;
 use crate::cfg::VariableCfg;
 use crate::cli_opt::*;
+use crate::engine::{value_renderer, Engine, Engines};
 use crate::error::*;
+use crate::text;
 use crate::variable_def::LabelValue;
 use crate::variable_def::VariableDef;
 use crate::FileOperation;
@@ -14,9 +17,9 @@ use dialoguer::theme::ColorfulTheme;
 use dialoguer::Confirm;
 use dialoguer::Input;
 use dialoguer::Select;
-use handlebars_misc_helpers::new_hbs;
 use lazy_static::lazy_static;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use tracing::{debug, instrument, span, warn, Level};
 
 lazy_static! {
@@ -25,7 +28,7 @@ lazy_static! {
     static ref PROMPT_THEME: ColorfulTheme = ColorfulTheme::default();
 }
 
-fn write_title(s: &str) -> Result<()> {
+pub(crate) fn write_title(s: &str) -> Result<()> {
     TERM.write_line(&format!("\n\n{}\n", TITLE_STYLE.apply_to(s)))?;
     Ok(())
 }
@@ -75,106 +78,116 @@ fn to_variabledef(v: &VariableCfg) -> Result<VariableDef> {
     })
 }
 
+/// ask the variables layer by layer, the values of a layer are stored under its namespace
 pub(crate) fn ask_variables(
     ctx: &Ctx,
-    list_variables: &[VariableCfg],
+    list_layers: &[LayerVariableCfgs],
     mut init: Variables,
 ) -> Result<Variables> {
     let mut variables = Variables::default();
     variables.append(&mut init);
-    let handlebars = new_hbs();
-
-    write_title("Configure variables")?;
-    // TODO optimize to reduce clones
-    for variable_cfg in list_variables.iter().cloned() {
-        let _span_ = span!(Level::DEBUG, "ask_variables", ?variable_cfg).entered();
-        if variables.contains_key(&variable_cfg.name) {
-            continue;
-        }
-        let render = |v: &str| {
-            let r = handlebars.render_template(v, &variables);
-            match r {
-                Ok(s) => s,
-                Err(e) => {
-                    warn!(input = ?v, error = ?e, "failed to convert");
-                    v.into()
-                }
+    let mut scopes = Vec::with_capacity(list_layers.len());
+    let engines = Engines::default();
+    for layer in list_layers {
+        let mut scope = layer.scope(&variables, &scopes)?;
+        for variable_cfg in &layer.variables {
+            if layer.is_defined(&variables, &variable_cfg.name) {
+                continue;
             }
-        };
-        let variable_cfg = variable_cfg.transforms_values(&render)?;
-        let variable = to_variabledef(&variable_cfg)?;
-        let name = variable.name;
-        let request = {
-            let prompt = if variable.ask.is_some() {
-                let ask = variable.ask.expect("variable ask should defined");
-                handlebars
-                    .render_template(&ask, &variables)
-                    .map_err(|source| Error::Handlebars {
-                        when: format!("define prompt for '{}'", &name),
-                        template: ask.clone(),
-                        source,
-                    })?
-            } else {
-                name.clone()
-            };
-            let values: Vec<String> = variable
-                .select_in_values
-                .iter()
-                .map(|v| v.label.to_string())
-                .collect::<Vec<String>>();
-            let default_value = variable
-                .default_value
-                .and_then(|default_value| Variables::value_as_str(&default_value).ok())
-                .map(|value| {
-                    let idx = variable
-                        .select_in_values
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(i, v)| {
-                            if v.label == value
-                                || Variables::value_as_str(&v.value)
-                                    .map(|v| v == value)
-                                    .unwrap_or(false)
-                            {
-                                Some(i)
-                            } else {
-                                None
-                            }
-                        })
-                        .next();
-                    VariableResponse { value, idx }
-                });
-            VariableRequest {
-                prompt,
-                default_value,
-                values,
+            // the value of a same name from an other namespace is not a value for this variable
+            scope.remove(&variable_cfg.name);
+            let engine = engines.get(layer.engine);
+            for (name, value) in ask_variable(ctx, variable_cfg.clone(), &scope, engine)? {
+                variables.insert_in(&layer.namespace, name.clone(), &value)?;
+                scope.insert(name, value)?;
             }
-        };
-        let resp = if variable.hidden || ctx.cmd_opt.no_interaction {
-            request.default_value.unwrap_or(VariableResponse {
-                value: "".to_owned(),
-                idx: None,
-            })
+        }
+        scopes.push(scope);
+    }
+    Ok(variables)
+}
+
+/// ask the value of the variable, return the values to store (the value and the selection details),
+/// `engine` renders the configuration of the variable
+fn ask_variable(
+    ctx: &Ctx,
+    variable_cfg: VariableCfg,
+    variables: &Variables,
+    engine: &dyn Engine,
+) -> Result<Vec<(String, serde_yaml::Value)>> {
+    let _span_ = span!(Level::DEBUG, "ask_variable", ?variable_cfg).entered();
+    let render = value_renderer(engine, variables, true);
+    let variable_cfg = variable_cfg.transforms_values(&render)?;
+    let variable = to_variabledef(&variable_cfg)?;
+    let name = variable.name;
+    let request = {
+        let prompt = if variable.ask.is_some() {
+            let ask = variable.ask.expect("variable ask should defined");
+            engine.render(&ask, variables, &format!("define prompt for '{}'", &name))?
         } else {
-            ask_variable_value(request)?
+            name.clone()
         };
-        if let Some(idx) = resp.idx {
-            variables.insert(format!("{}__idx", name), idx)?;
-            variables.insert(format!("{}__label", name), resp.value)?;
-            variables.insert(
-                name.clone(),
+        let values: Vec<String> = variable
+            .select_in_values
+            .iter()
+            .map(|v| v.label.to_string())
+            .collect::<Vec<String>>();
+        let default_value = variable
+            .default_value
+            .and_then(|default_value| Variables::value_as_str(&default_value).ok())
+            .map(|value| {
+                let idx = variable
+                    .select_in_values
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, v)| {
+                        if v.label == value
+                            || Variables::value_as_str(&v.value)
+                                .map(|v| v == value)
+                                .unwrap_or(false)
+                        {
+                            Some(i)
+                        } else {
+                            None
+                        }
+                    })
+                    .next();
+                VariableResponse { value, idx }
+            });
+        VariableRequest {
+            prompt,
+            default_value,
+            values,
+        }
+    };
+    let resp = if variable.hidden || ctx.cmd_opt.no_interaction {
+        request.default_value.unwrap_or(VariableResponse {
+            value: "".to_owned(),
+            idx: None,
+        })
+    } else {
+        ask_variable_value(request)?
+    };
+    if let Some(idx) = resp.idx {
+        Ok(vec![
+            (format!("{}__idx", name), serde_yaml::to_value(idx)?),
+            (
+                format!("{}__label", name),
+                serde_yaml::Value::String(resp.value),
+            ),
+            (
+                name,
                 variable
                     .select_in_values
                     .get(idx)
                     .expect("selected should be in the list")
                     .value
                     .clone(),
-            )?;
-        } else {
-            variables.insert(name.clone(), Variables::value_from_str(&resp.value)?)?;
-        }
+            ),
+        ])
+    } else {
+        Ok(vec![(name, Variables::value_from_str(&resp.value)?)])
     }
-    Ok(variables)
 }
 
 pub fn ask_variable_value(req: VariableRequest) -> Result<VariableResponse> {
@@ -215,14 +228,20 @@ fn format_operation(op: &FileOperation) -> Cow<'static, str> {
         FileOperation::Nothing => "do nothing",
         FileOperation::Ignore => "ignore",
         FileOperation::MkDir => "make dir",
+        FileOperation::MkSymlink => "make symlink",
         FileOperation::AddFile => "add file",
         FileOperation::UpdateFile => "update file",
+        FileOperation::MoveFile { .. } => "move file",
+        FileOperation::DeleteFile => "delete file",
+        FileOperation::PatchFile { .. } => "patch file",
     };
     console::pad_str(s, 15, console::Alignment::Left, Some("..."))
 }
 
 //TODO add flag to filter display: all, changes, none
-pub fn confirm_plan(ctx: &Ctx, actions: &[Action]) -> Result<bool> {
+/// show the plan, with the layers (`layers_labels` by order) that provide each file
+/// when the template is composed of several layers
+pub fn confirm_plan(ctx: &Ctx, actions: &[Action], layers_labels: &[String]) -> Result<bool> {
     write_title("Plan to execute")?;
     debug!(?actions, "plan");
     let prefixes = tree::provide_prefix(actions, |parent, item| {
@@ -230,17 +249,47 @@ pub fn confirm_plan(ctx: &Ctx, actions: &[Action]) -> Result<bool> {
     });
     for (a, prefix) in actions.iter().zip(prefixes.iter()) {
         let p = a.dst_path.base.join(&a.dst_path.relative);
+        let origin = if let FileOperation::MoveFile { from } = &a.operation {
+            format!("  \x1B[2m<- {}\x1B[0m", from.to_string_lossy())
+        } else if layers_labels.len() > 1
+            && !a.src.is_empty()
+            && a.operation != FileOperation::MkDir
+        {
+            let labels = a
+                .src
+                .iter()
+                .filter_map(|src| layers_labels.get(src.layer_order))
+                .map(|l| l.as_str())
+                .collect::<Vec<_>>();
+            format!("  \x1B[2m<- {}\x1B[0m", labels.join(" + "))
+        } else {
+            "".to_owned()
+        };
         let s = format!(
-            "   - {} \x1B[38;2;{};{};{}m{}\x1B[0m{}",
+            "   - {} \x1B[38;2;{};{};{}m{}\x1B[0m{}{}",
             format_operation(&a.operation),
             80,
             80,
             80,
             prefix,
             p.file_name().and_then(|v| v.to_str()).unwrap_or("???"),
+            origin,
         );
         TERM.write_line(&s)?;
     }
+    // the formatters to run (confirmed later, once by command), with their number of files
+    let mut formatters: BTreeMap<&str, usize> = BTreeMap::new();
+    for cmd in actions.iter().filter_map(|a| a.formatter()) {
+        *formatters.entry(cmd).or_default() += 1;
+    }
+    for (cmd, count) in formatters {
+        TERM.write_line(&format!(
+            "   - {} {}  \x1B[2m({} files)\x1B[0m",
+            console::pad_str("run formatter", 15, console::Alignment::Left, Some("...")),
+            cmd,
+            count
+        ))?;
+    }
     let r = if ctx.cmd_opt.confirm == AskConfirmation::Always {
         Confirm::with_theme(&(*PROMPT_THEME))
             .with_prompt("Do you want to apply plan ?")
@@ -258,8 +307,27 @@ where
 {
     use difference::Changeset;
     use std::fs;
-    let local_str = fs::read_to_string(&local)?;
-    let remote_str = fs::read_to_string(&remote)?;
+    let local_content = fs::read(&local)?;
+    let remote_content = fs::read(&remote)?;
+    if text::is_binary(&local_content) || text::is_binary(&remote_content) {
+        // binary files are compared by size and hash
+        for (label, content) in [("local", &local_content), ("remote", &remote_content)] {
+            println!(
+                "{}: {} bytes, md5 {:x}",
+                label,
+                content.len(),
+                md5::compute(content)
+            );
+        }
+        return Ok(());
+    }
+    // the line endings are ignored, to not show every line as changed
+    let local_str = text::decode(&local_content).0.replace("\r\n", "\n");
+    let remote_str = text::decode(&remote_content).0.replace("\r\n", "\n");
+    if local_str == remote_str {
+        println!("only the line endings differ");
+        return Ok(());
+    }
     let changeset = Changeset::new(&local_str, &remote_str, "\n");
     println!("{}", changeset);
     Ok(())
@@ -306,6 +374,27 @@ pub fn show_message(
     Ok(())
 }
 
+/// the output (stdout & stderr) of the commands of a script, shown at the end of the script (buffered)
+pub fn show_script_output(
+    _ctx: &Ctx,
+    template_name: impl std::fmt::Display,
+    stdout: &str,
+    stderr: &str,
+) -> Result<()> {
+    for (label, content) in [("output", stdout), ("errors", stderr)] {
+        if !content.trim().is_empty() {
+            println!(
+                "\n {} of the commands from template: {}",
+                label, template_name
+            );
+            for line in content.lines() {
+                println!("\t{}", line);
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn confirm_run_script(
     ctx: &Ctx,
     template_name: impl std::fmt::Display,
@@ -0,0 +1,144 @@
+use crate::error::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::warn;
+
+const USER_CFG_FILENAME: &str = "config.yaml";
+
+/// configuration of the user (not of the template),
+/// stored into the config folder of the application (see `ffizer inspect`)
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields, default)]
+pub struct UserCfg {
+    /// settings to access git repositories, by host (eg: `github.com`)
+    pub hosts: BTreeMap<String, HostCfg>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields, default)]
+pub struct HostCfg {
+    /// private key to use for ssh remotes (`~/` is expanded)
+    pub ssh_key: Option<PathBuf>,
+    /// username to use with the token for https remotes
+    pub username: Option<String>,
+    /// name of the environment variable that provides the token for https remotes
+    pub token_env: Option<String>,
+}
+
+impl UserCfg {
+    pub fn find_user_cfg_file() -> Result<PathBuf> {
+        let app_name = env!("CARGO_PKG_NAME");
+        let project_dirs = directories::ProjectDirs::from("", app_name, app_name)
+            .ok_or(crate::Error::ApplicationPathNotFound {})?;
+        Ok(project_dirs.config_dir().join(USER_CFG_FILENAME))
+    }
+
+    /// load the user configuration, or the default one if the file doesn't exist
+    pub fn load() -> Result<UserCfg> {
+        let path = Self::find_user_cfg_file()?;
+        if path.exists() {
+            let cfg_str = fs::read_to_string(&path).map_err(|source| Error::ReadFile {
+                path: path.clone(),
+                source,
+            })?;
+            UserCfg::from_str(&cfg_str)
+        } else {
+            Ok(UserCfg::default())
+        }
+    }
+
+    /// load the user configuration, a malformed file is reported and replaced by the default one
+    /// (to not block the templates of hosts it doesn't configure)
+    pub fn load_or_default() -> UserCfg {
+        Self::load().unwrap_or_else(|err| {
+            warn!(
+                ?err,
+                "failed to load the user configuration, use the default one"
+            );
+            UserCfg::default()
+        })
+    }
+
+    pub fn find_host(&self, host: &str) -> Option<&HostCfg> {
+        self.hosts.get(host)
+    }
+}
+
+impl FromStr for UserCfg {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        serde_yaml::from_str::<UserCfg>(s).map_err(Error::from)
+    }
+}
+
+impl HostCfg {
+    pub fn ssh_key_path(&self) -> Option<PathBuf> {
+        self.ssh_key.as_ref().map(|p| expand_home(p))
+    }
+
+    /// the value of the environment variable `token_env`, read with `env`
+    pub fn token<F>(&self, env: F) -> Option<String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        self.token_env
+            .as_deref()
+            .and_then(env)
+            .filter(|v| !v.is_empty())
+    }
+}
+
+fn expand_home(path: &std::path::Path) -> PathBuf {
+    match (
+        path.strip_prefix("~"),
+        directories::BaseDirs::new().map(|d| d.home_dir().to_path_buf()),
+    ) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_deserialize_user_cfg_yaml() {
+        let cfg_str = r#"
+        hosts:
+            github.com:
+                ssh_key: /keys/id_github
+            gitlab.example.com:
+                username: oauth2
+                token_env: GITLAB_TOKEN
+        "#;
+        let actual = UserCfg::from_str(cfg_str).unwrap();
+        assert_eq!(
+            actual
+                .find_host("github.com")
+                .and_then(|h| h.ssh_key_path()),
+            Some(PathBuf::from("/keys/id_github"))
+        );
+        let gitlab = actual.find_host("gitlab.example.com").unwrap();
+        assert_eq!(gitlab.username.as_deref(), Some("oauth2"));
+        assert_eq!(gitlab.token_env.as_deref(), Some("GITLAB_TOKEN"));
+        assert_eq!(actual.find_host("bitbucket.org"), None);
+    }
+
+    #[test]
+    fn test_expand_home() {
+        assert_eq!(expand_home(&PathBuf::from("/foo")), PathBuf::from("/foo"));
+        let home = directories::BaseDirs::new()
+            .unwrap()
+            .home_dir()
+            .to_path_buf();
+        assert_eq!(
+            expand_home(&PathBuf::from("~/.ssh/id_rsa")),
+            home.join(".ssh/id_rsa")
+        );
+    }
+}
@@ -20,6 +20,75 @@ impl Variables {
         self.0.contains_key(&key.into())
     }
 
+    pub fn get(&self, key: &str) -> Option<&serde_yaml::Value> {
+        self.0.get(key)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<serde_yaml::Value> {
+        self.0.remove(key)
+    }
+
+    /// insert the value under the namespace (eg: `["svc_a"]` to store as `svc_a.key`)
+    pub fn insert_in<K: Into<String>, V: Serialize>(
+        &mut self,
+        namespace: &[String],
+        key: K,
+        value: V,
+    ) -> Result<()> {
+        let (first, others) = match namespace.split_first() {
+            None => return self.insert(key, value),
+            Some(v) => v,
+        };
+        let mut current = self
+            .0
+            .entry(first.to_owned())
+            .or_insert_with(|| serde_yaml::Value::Mapping(serde_yaml::Mapping::new()));
+        for name in others {
+            current = as_mapping_mut(current)
+                .entry(serde_yaml::Value::String(name.to_owned()))
+                .or_insert_with(|| serde_yaml::Value::Mapping(serde_yaml::Mapping::new()));
+        }
+        as_mapping_mut(current).insert(
+            serde_yaml::Value::String(key.into()),
+            serde_yaml::to_value(value)?,
+        );
+        Ok(())
+    }
+
+    pub fn contains_key_in(&self, namespace: &[String], key: &str) -> bool {
+        match namespace.split_first() {
+            None => self.0.contains_key(key),
+            Some((first, others)) => {
+                let mut current = self.0.get(first);
+                for name in others {
+                    current = current.and_then(|v| v.get(name.as_str()));
+                }
+                current.and_then(|v| v.get(key)).is_some()
+            }
+        }
+    }
+
+    /// the variables with the values of the namespace (and of its parents) at the top level
+    pub fn scoped(&self, namespace: &[String]) -> Variables {
+        let mut back = self.clone();
+        let mut current: Option<&serde_yaml::Value> = None;
+        for (i, name) in namespace.iter().enumerate() {
+            current = if i == 0 {
+                self.0.get(name)
+            } else {
+                current.and_then(|v| v.get(name.as_str()))
+            };
+            if let Some(serde_yaml::Value::Mapping(m)) = current {
+                for (k, v) in m {
+                    if let Some(k) = k.as_str() {
+                        back.0.insert(k.to_owned(), v.clone());
+                    }
+                }
+            }
+        }
+        back
+    }
+
     #[instrument]
     pub fn value_from_str(s: &str) -> Result<serde_yaml::Value> {
         serde_yaml::from_str::<serde_yaml::Value>(s).map_err(Error::from)
@@ -33,6 +102,14 @@ impl Variables {
     }
 }
 
+/// replace the value by an empty mapping if it's not a mapping
+fn as_mapping_mut(v: &mut serde_yaml::Value) -> &mut serde_yaml::Mapping {
+    if !v.is_mapping() {
+        *v = serde_yaml::Value::Mapping(serde_yaml::Mapping::new());
+    }
+    v.as_mapping_mut().expect("value should be a mapping")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -51,4 +128,23 @@ mod tests {
         assert_that!(&Variables::value_from_str("42").unwrap())
             .is_equal_to(&serde_yaml::to_value(42).unwrap());
     }
+
+    #[test]
+    fn test_scoped_by_namespace() {
+        let mut variables = Variables::default();
+        variables.insert("name", "root").unwrap();
+        variables.insert("project", "prj").unwrap();
+        let svc_a = vec!["svc_a".to_owned()];
+        variables.insert_in(&svc_a, "name", "api").unwrap();
+        assert_that!(variables.contains_key_in(&svc_a, "name")).is_true();
+        assert_that!(variables.contains_key_in(&svc_a, "project")).is_false();
+        assert_that!(variables.contains_key_in(&[], "project")).is_true();
+
+        let scoped = variables.scoped(&svc_a);
+        assert_that!(scoped.0.get("name")).is_equal_to(Some(&serde_yaml::to_value("api").unwrap()));
+        assert_that!(scoped.0.get("project"))
+            .is_equal_to(Some(&serde_yaml::to_value("prj").unwrap()));
+        let root = variables.scoped(&[]);
+        assert_that!(root.0.get("name")).is_equal_to(Some(&serde_yaml::to_value("root").unwrap()));
+    }
 }
@@ -0,0 +1,3 @@
+// my-project by ffizer
+// SPDX-License-Identifier: MIT
+fn main() {}
@@ -0,0 +1,2 @@
+// my-project
+fn main() {}
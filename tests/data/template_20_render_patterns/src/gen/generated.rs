@@ -0,0 +1 @@
+// {{ not_rendered }}
@@ -0,0 +1,2 @@
+// {{ project_name }}
+fn main() {}
@@ -0,0 +1,3 @@
+// my-project (my_project)
+pub const A: &str = "a";
+pub const B: &str = "b";